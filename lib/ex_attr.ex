@@ -26,70 +26,100 @@ defmodule ExAttr do
   your namespace a child of the `user` namespace (ex: `"user.<my_namespace>"`)
   unless you are doing SELinux stuff.
 
-  This is most easily abstracted away by simply wrapping the functions of this library
-  to auto handle all that for you.
-
-  Here is an example of what I mean:
+  This is most easily abstracted away with `ExAttr.Namespace`, which generates wrappers
+  around the functions of this library that auto handle all that for you:
   ```elixir
   defmodule MyApp.XAttr do
-    @namespace "user.my_apps_namespace"
+    use ExAttr.Namespace, prefix: "user.my_apps_namespace"
+  end
 
-    def set(path, name, value) do
-      name = "\#{@namespace}.\#{name}"
-      ExAttr.set(path, name, value)
-    end
+  MyApp.XAttr.set("test.txt", "foo", "bar")
+  # => sets "user.my_apps_namespace.foo"
+  ```
 
-    def get(path, name) do
-      name = "\#{@namespace}.\#{name}"
-      ExAttr.get(path, name)
-    end
+  ### Raw Names
+  Paths and attribute names are handed to the filesystem as raw bytes and attribute names
+  are listed back as raw binaries, so neither has to be valid UTF-8. This makes it possible
+  to manage attributes on arbitrary trees, such as user uploaded files.
 
-    def list(path) do
-      case ExAttr.list(path) do
-        {:ok, list} ->
-          list
-          |> Enum.filter(&match?("\#{@namespace}." <> _, &1))
-          |> Enum.map(fn "\#{@namespace}." <> name -> name end)
-          |> then(fn list ->
-            {:ok, list}
-          end)
+  ### Symlinks
+  By default every operation applies to a symlink itself rather than to the file it points
+  to, matching the behaviour of `lgetxattr(2)` and friends. Pass `follow_symlinks: true` to
+  any function to operate on the symlink's target instead.
 
-        {:error, reason} ->
-          {:error, reason}
-      end
-    end
+  Keep in mind that Linux does not permit `user.*` attributes on symlinks, so setting one
+  on a symlink without following it will fail with `:eperm`.
 
-    # And so on...
-  end
-  ```
+  ### Handles
+  Every function resolves the path it is given on each call. When managing many
+  attributes on one file, open an `ExAttr.Handle` once and pass it to `get`, `set`,
+  `remove`, `list` and `dump` in place of the path. The handle keeps applying to the same
+  file even if it is renamed or replaced, and is closed once garbage collected.
 
   ### Error Handling
-  When possible, POSIX errors are normalized as atoms to be compatible with the error
-  semantics of the `File` & `:file` modules. If it's either not a POSIX error, or one I
-  never anticipated, then it will fallback to being a generic string error instead.
+  POSIX errors are normalized as their lowercase atoms (ex: `ENOENT` -> `:enoent`) to be
+  compatible with the error semantics of the `File` & `:file` modules. Every errno known to
+  the platform is mapped this way, only errors that don't come from the OS at all fallback
+  to being a generic string error instead.
+
+  The non-bang functions only return the reason, while the bang functions raise an
+  `ExAttr.Error` that also carries the raw errno, the syscall that failed and the
+  attribute name it failed on, which makes it easy to group failures by cause.
 
   Since these align with existing semantics of erlang, you can use `:file.format_error/1` to
   get the error string for any atoms returned here. Alternativey you can get them directly
   via `:erl_posix_msg.message/1` which `:file.format_error/1` uses under the hood.
 
-  Below is a table that lists all possible POSIX errors and what could trigger them, the
-  description used might not align with what `:file.format_error/1` returns.
-
-  | Error    | Description |
-  |----------|-------------|
-  | :e2big   | Attribute value is too big |
-  | :eacces  | Permission denied |
-  | :einval  | Invalid argument |
-  | :eio     | Generic I/O Error |
-  | :enodata | Attribute does not exist (aliased with enoattr) |
-  | :enoent  | Does not exist |
-  | :enomem  | Out of memory issue, rare |
-  | :enospc  | No space on device |
-  | :eperm   | Operation not permitted |
-  | :erofs   | Read-only filesystem |
-  | :enotsup | Filesystem or platform not supported |
-  """
-  alias ExAttr.Nif
+  Below is a table that lists the POSIX errors you are most likely to run into and what
+  could trigger them, the description used might not align with what `:file.format_error/1`
+  returns.
+
+  | Error         | Description |
+  |---------------|-------------|
+  | :e2big        | Attribute value is too big |
+  | :eacces       | Permission denied |
+  | :ebadf        | Bad file descriptor |
+  | :edquot       | Disk quota exceeded |
+  | :eexist       | Attribute already exists (only with `mode: :create`) |
+  | :efault       | Bad address |
+  | :einval       | Invalid argument |
+  | :eio          | Generic I/O Error |
+  | :eloop        | Too many levels of symbolic links |
+  | :emfile       | Too many open files |
+  | :enametoolong | Path or attribute name is too long |
+  | :enodata      | Attribute does not exist (aliased with enoattr) |
+  | :enoent       | Does not exist |
+  | :enomem       | Out of memory issue, rare |
+  | :enospc       | No space on device |
+  | :enotdir      | A component of the path is not a directory |
+  | :eperm        | Operation not permitted |
+  | :erange       | Attribute value or name list is too big for the buffer |
+  | :erofs        | Read-only filesystem |
+  | :enotsup      | Filesystem or platform not supported |
+
+  Names and values are checked before they reach the kernel, which would otherwise report
+  most bad input as an ambiguous `:einval` or `:erange`. These failures are returned as
+  `{:invalid_name, kind}` or `{:invalid_value, kind}` and have a `nil` errno.
+
+  | Error                               | Description |
+  |-------------------------------------|-------------|
+  | {:invalid_name, :empty}             | Name is empty |
+  | {:invalid_name, :too_long}          | Name is longer than 255 bytes |
+  | {:invalid_name, :nul_byte}          | Name contains a NUL byte |
+  | {:invalid_name, :no_namespace}      | Name has no namespace (ex: `"foo"` instead of `"user.foo"`) |
+  | {:invalid_name, :unknown_namespace} | Namespace is not `user`, `trusted`, `security` or `system` |
+  | {:invalid_name, :empty_key}         | Name is only a namespace (ex: `"user."`) |
+  | {:invalid_value, :too_big}          | Value is bigger than 64KiB (Linux only) |
+
+  The namespace checks are skipped on macOS, where names have no namespaces.
+
+  Dumps passed to `import/2` that can't be parsed fail with `{:invalid_dump, line}`, the
+  number of the first offending line. Snapshots passed to `restore/2` fail with
+  `{:invalid_snapshot, :malformed}` if they aren't JSON or CBOR at all,
+  `{:invalid_snapshot, :schema}` if they don't follow the schema and
+  `{:invalid_snapshot, :version}` if they are of an unknown version.
+  """
+  alias ExAttr.{Handle, Name, Nif}
 
   ##################
   #   Exceptions   #
@@ -102,11 +132,14 @@ defmodule ExAttr do
     The following fields of this exception are public and can be accessed freely:
 
       * `:path` (`t:Path.t/0`) - the path of the file that caused the error
-      * `:reason` (`t:String.t/0`) - the reason for the error
+      * `:reason` (`t:ExAttr.error_reason/0`) - the reason for the error
+      * `:errno` (`t:integer/0` | `nil`) - the raw errno, `nil` if the error didn't come from the OS
+      * `:syscall` (`t:atom/0` | `nil`) - the syscall that failed (ex: `:lgetxattr`)
+      * `:name` (`t:ExAttr.name/0` | `nil`) - the attribute the syscall failed on, if any
 
     """
 
-    defexception [:reason, :path, action: ""]
+    defexception [:reason, :path, :errno, :syscall, :name, action: ""]
 
     @impl true
     def message(%{action: action, reason: reason, path: path}) do
@@ -123,6 +156,18 @@ defmodule ExAttr do
     def format(posix_err) when is_atom(posix_err) do
       "#{posix_err} :: #{:file.format_error(posix_err)}"
     end
+    def format({:invalid_name, kind}) do
+      "invalid attribute name :: #{kind}"
+    end
+    def format({:invalid_value, kind}) do
+      "invalid attribute value :: #{kind}"
+    end
+    def format({:invalid_dump, line}) do
+      "invalid dump :: line #{line}"
+    end
+    def format({:invalid_snapshot, kind}) do
+      "invalid snapshot :: #{kind}"
+    end
     def format(reason) when is_binary(reason), do: reason
 
   end
@@ -131,25 +176,246 @@ defmodule ExAttr do
   #   Types   #
   #############
 
-  @type name()  :: String.t()
-  @type value() :: String.t() | nil
+  @type name()  :: binary()
+  @type value() :: binary() | nil
+
+  @typedoc """
+  Options accepted by every function:
+
+    * `:follow_symlinks` - operate on the target of a symlink instead of the symlink
+      itself. Defaults to `false`.
+  """
+  @type option  :: {:follow_symlinks, boolean()}
+  @type options :: [option()]
+
+  @typedoc """
+  Options accepted by `list/2` and `dump/2` on top of `t:option/0`:
+
+    * `:prefix` - only list the names starting with this prefix, the filtering is done
+      natively so the other names are never copied over.
+    * `:strip_prefix` - remove the `:prefix` from the listed names. Defaults to `false`.
+    * `:parse_names` - return names as `ExAttr.Name` structs instead of binaries, parsed
+      natively. Can't be combined with `:strip_prefix`. Defaults to `false`.
+  """
+  @type list_option  ::
+      option()
+    | {:prefix, name()}
+    | {:strip_prefix, boolean()}
+    | {:parse_names, boolean()}
+  @type list_options :: [list_option()]
+
+  @typedoc """
+  Options accepted by `dump_tree/2` on top of `t:list_option/0`:
+
+    * `:max_depth` - how deep to descend, the root being at depth `0`. Defaults to no limit.
+    * `:cross_mounts` - descend into directories on other filesystems than the root's.
+      Mount points are listed either way. Defaults to `true`.
+    * `:threads` - scan with this many native worker threads instead of a single dirty
+      scheduler. Results are then no longer in walk order.
+    * `:batch_size` - how many files a parallel scan sends back per message, or a stream
+      reads per step. Defaults to `1000`.
+
+  With `:follow_symlinks`, symlinks to directories are descended into as well (every
+  directory only once, so cycles terminate) and dangling symlinks are skipped.
+  """
+  @type tree_option  ::
+      list_option()
+    | {:max_depth, non_neg_integer()}
+    | {:cross_mounts, boolean()}
+    | {:threads, pos_integer()}
+    | {:batch_size, pos_integer()}
+  @type tree_options :: [tree_option()]
+
+  @type set_mode    :: :create | :replace | :upsert
+  @typedoc """
+  Options accepted by `set/4` on top of `t:option/0`:
+
+    * `:mode` - how to treat an attribute that may already exist. `:create` fails with
+      `:eexist` if it does, `:replace` fails with `:enodata` if it doesn't and `:upsert`
+      doesn't care. Defaults to `:upsert`.
+  """
+  @type set_option  :: option() | {:mode, set_mode()}
+  @type set_options :: [set_option()]
 
   @type result(t) :: {:ok, t} | {:error, error_reason()}
   @type result    :: :ok      | {:error, error_reason()}
 
+  @typedoc """
+  Options accepted by `copy/3` on top of `t:option/0`:
+
+    * `:namespaces` - only copy attributes in these namespaces (ex: `[:user]`). Defaults
+      to every namespace.
+    * `:prefix` - only copy attributes whose name starts with this prefix.
+    * `:on_conflict` - `:overwrite` attributes the destination already has, or `:skip`
+      them. Defaults to `:overwrite`.
+    * `:remove_extra` - remove the destination's attributes that the source doesn't have,
+      limited to the selected namespaces and prefix. Defaults to `false`.
+  """
+  @type copy_option ::
+      option()
+    | {:namespaces, [Name.namespace()]}
+    | {:prefix, name()}
+    | {:on_conflict, :overwrite | :skip}
+    | {:remove_extra, boolean()}
+  @type copy_options :: [copy_option()]
+
+  @typedoc """
+  What `copy/3` did with every attribute it touched.
+  """
+  @type copy_report :: %{
+          copied: [name()],
+          skipped: [name()],
+          removed: [name()],
+          failed: [{name(), error_reason()}]
+        }
+
+  @typedoc """
+  Options accepted by `diff/3` on top of `t:option/0`:
+
+    * `:prefix` - only compare attributes whose name starts with this prefix.
+  """
+  @type diff_option  :: option() | {:prefix, name()}
+  @type diff_options :: [diff_option()]
+
+  @typedoc """
+  One side of `diff/3`, a path or a map of names to values as returned by `dump/2`.
+  """
+  @type diff_side :: Path.t() | %{name() => binary()}
+
+  @typedoc """
+  How the attributes of two files differ, see `diff/3`.
+  """
+  @type diff :: %{
+          added: %{name() => binary()},
+          removed: %{name() => binary()},
+          changed: %{name() => {binary(), binary()}}
+        }
+
+  @typedoc """
+  Options accepted by `export/2` on top of `t:option/0`:
+
+    * `:prefix`, `:max_depth` and `:cross_mounts` - as for `dump_tree/2`.
+    * `:encoding` - how values are written, `:text`, `:hex` or `:base64` like
+      `getfattr --encoding`. Defaults to text unless a value has too many unprintable
+      bytes, in which case it is written as base64, the same way getfattr picks.
+  """
+  @type export_option  ::
+      option()
+    | {:prefix, name()}
+    | {:max_depth, non_neg_integer()}
+    | {:cross_mounts, boolean()}
+    | {:encoding, :text | :hex | :base64}
+  @type export_options :: [export_option()]
+
+  @type snapshot_format :: :json | :cbor
+
+  @typedoc """
+  Options accepted by `snapshot/2` on top of `t:option/0`:
+
+    * `:format` - `:json` or `:cbor`. Defaults to `:json`.
+    * `:prefix`, `:max_depth` and `:cross_mounts` - as for `dump_tree/2`.
+  """
+  @type snapshot_option  ::
+      option()
+    | {:format, snapshot_format()}
+    | {:prefix, name()}
+    | {:max_depth, non_neg_integer()}
+    | {:cross_mounts, boolean()}
+  @type snapshot_options :: [snapshot_option()]
+
+  @typedoc """
+  Options accepted by `restore/2` on top of `t:option/0`:
+
+    * `:format` - `:json` or `:cbor`. Defaults to `:json`.
+  """
+  @type restore_option  :: option() | {:format, snapshot_format()}
+  @type restore_options :: [restore_option()]
+
+  @typedoc """
+  Options accepted by `list_tar/2` and `extract_tar/3`:
+
+    * `:prefix` - only list or set attributes whose name starts with this prefix.
+      Defaults to `"user."`, pass `nil` for every attribute.
+  """
+  @type tar_option :: {:prefix, name() | nil}
+
+  @typedoc """
+  What `extract_tar/3` couldn't set, by extracted file, in the same shape as the
+  `:failed` field of `t:copy_report/0`.
+  """
+  @type extract_tar_report :: %{failed: [{Path.t(), [{name(), error_reason()}]}]}
+
+  @typedoc """
+  Options accepted by `create_tar/3` on top of `t:option/0`:
+
+    * `:prefix` - only archive attributes whose name starts with this prefix. Defaults
+      to `"user."`, pass `nil` to archive every attribute.
+    * `:max_depth` and `:cross_mounts` - as for `dump_tree/2`.
+  """
+  @type create_tar_option  ::
+      option()
+    | {:prefix, name() | nil}
+    | {:max_depth, non_neg_integer()}
+    | {:cross_mounts, boolean()}
+  @type create_tar_options :: [create_tar_option()]
+
+  @typedoc """
+  Options accepted by `cp/3`:
+
+    * `:namespaces` - only copy attributes in these namespaces (ex: `[:user]`). Defaults
+      to every namespace.
+    * `:prefix` - only copy attributes whose name starts with this prefix.
+    * `:on_error` - `:fail` to leave the destination untouched if any attribute can't be
+      set, or `:skip` to copy the file without them and report them under `:failed`.
+      Defaults to `:fail`.
+  """
+  @type cp_option ::
+      {:namespaces, [Name.namespace()]}
+    | {:prefix, name()}
+    | {:on_error, :fail | :skip}
+  @type cp_options :: [cp_option()]
+
+  @typedoc """
+  A condition on a file's attributes, see `find/3`.
+  """
+  @type predicate ::
+      {:exists, name()}
+    | {:missing, name()}
+    | {:equals, name(), binary()}
+    | {:prefix, name(), binary()}
+    | {:regex, name(), Regex.t() | String.t()}
+    | {:size, name(), :< | :<= | :== | :!= | :>= | :>, non_neg_integer()}
+    | {:and, [predicate()]}
+    | {:or, [predicate()]}
+    | {:not, predicate()}
+
   @type error_reason ::
       String.t()
-    | :e2big   # Attribute is too big
-    | :eacces  # Permission denied
-    | :einval  # Invalid argument
-    | :eio     # Generic I/O Error
-    | :enodata # Attribute does not exist (aliased with enoattr)
-    | :enoent  # Does not exist
-    | :enomem  # Out of memory issue, rare
-    | :enospc  # No space on device
-    | :eperm   # Operation not permitted
-    | :erofs   # When read-only filesystem
-    | :enotsup # When filesystem or platform not supported
+    | :e2big        # Attribute is too big
+    | :eacces       # Permission denied
+    | :ebadf        # Bad file descriptor
+    | :edquot       # Disk quota exceeded
+    | :eexist       # Attribute already exists
+    | :efault       # Bad address
+    | :einval       # Invalid argument
+    | :eio          # Generic I/O Error
+    | :eloop        # Too many levels of symbolic links
+    | :emfile       # Too many open files
+    | :enametoolong # Path or attribute name is too long
+    | :enodata      # Attribute does not exist (aliased with enoattr)
+    | :enoent       # Does not exist
+    | :enomem       # Out of memory issue, rare
+    | :enospc       # No space on device
+    | :enotdir      # A component of the path is not a directory
+    | :eperm        # Operation not permitted
+    | :erange       # Value or name list too big for the buffer
+    | :erofs        # When read-only filesystem
+    | :enotsup      # When filesystem or platform not supported
+    | atom()        # Any other POSIX error
+    | {:invalid_name, :empty | :too_long | :nul_byte | :no_namespace | :unknown_namespace | :empty_key}
+    | {:invalid_value, :too_big}
+    | {:invalid_dump, pos_integer()}
+    | {:invalid_snapshot, :malformed | :schema | :version}
 
   #################
   #   Functions   #
@@ -169,6 +435,9 @@ defmodule ExAttr do
   @doc """
   Get an extended attribute for the specified file.
 
+  Values are returned as raw binaries exactly as stored, they are not required to be
+  valid UTF-8.
+
   ## Examples
   ```elixir
   iex> ExAttr.get("test.txt", "user.foo")
@@ -179,42 +448,51 @@ defmodule ExAttr do
   {:ok, "123"}
   ```
   """
-  @spec get(Path.t(), name()) :: result(value())
-  def get(path, name) do
-    case Nif.get_xattr(path, name) do
-      {:error, reason} ->
-        {:error, reason}
-
-      nil ->
-        {:ok, nil}
-
-      error when is_atom(error) ->
-        {:error, error}
-
-      value ->
-        {:ok, to_string(value)}
-    end
+  @spec get(Path.t() | Handle.t(), name(), options()) :: result(value())
+  def get(path, name, opts \\ []) do
+    path |> do_get(name, opts) |> to_reason()
   end
 
   @doc """
   Get an extended attribute for the specified file, raises on error
   """
-  @spec get!(Path.t(), name()) :: value()
-  def get!(path, name) do
-    case get(path, name) do
-      {:error, reason} ->
-        raise Error,
-          action: "get xattr #{inspect name} from",
-          path: path,
-          reason: reason
+  @spec get!(Path.t() | Handle.t(), name(), options()) :: value()
+  def get!(path, name, opts \\ []) do
+    case do_get(path, name, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "get xattr #{inspect name} from"}
 
       {:ok, value} -> value
     end
   end
 
+  defp do_get(%Handle{ref: ref} = handle, name, _opts) do
+    case Nif.handle_get_xattr(ref, name) do
+      {:error, details} ->
+        nif_error(details, handle)
+
+      value ->
+        {:ok, value}
+    end
+  end
+  defp do_get(path, name, opts) do
+    case Nif.get_xattr(path, name, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      value ->
+        {:ok, value}
+    end
+  end
+
   @doc """
   Set an extended attribute on the specified file. Passing a `nil` value will remove the attribute.
 
+  The `:mode` option makes the set atomic at the syscall level: with `:create` it only
+  succeeds if the attribute doesn't exist yet and with `:replace` only if it already does.
+  When removing with a `nil` value, `:replace` returns `{:error, :enodata}` if there was
+  nothing to remove, otherwise a missing attribute is not considered an error.
+
   ## Examples
   ```elixir
   iex> ExAttr.set("test.txt", "user.foo", "123")
@@ -227,40 +505,51 @@ defmodule ExAttr do
   {:ok, nil}
   ```
   """
-  @spec set(Path.t(), name(), value()) :: result()
-  def set(path, name, nil) do
-    case remove(path, name) do
+  @spec set(Path.t() | Handle.t(), name(), value(), set_options()) :: result()
+  def set(path, name, value, opts \\ []) do
+    path |> do_set(name, value, opts) |> to_reason()
+  end
+
+  @doc """
+  Set an extended attribute on the specified file. Passing a `nil` value will remove the attribute, raises on error.
+  """
+  @spec set!(Path.t() | Handle.t(), name(), value(), set_options()) :: :ok
+  def set!(path, name, value, opts \\ []) do
+    case do_set(path, name, value, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "set xattr #{inspect name} -> #{inspect value} for"}
+
       :ok -> :ok
-      {:error, :enodata} -> :ok
-      {:error, reason} -> {:error, reason}
     end
   end
-  def set(path, name, value) do
-    case Nif.set_xattr(path, name, to_string(value)) do
+
+  defp do_set(path, name, nil, opts) do
+    case {do_remove(path, name, opts), Keyword.get(opts, :mode, :upsert)} do
+      {:ok, _mode} -> :ok
+      {{:error, %Error{reason: :enodata}}, mode} when mode != :replace -> :ok
+      {error, _mode} -> error
+    end
+  end
+  defp do_set(%Handle{ref: ref} = handle, name, value, opts) do
+    mode = Keyword.get(opts, :mode, :upsert)
+
+    case Nif.handle_set_xattr(ref, name, to_string(value), mode) do
       :ok ->
         :ok
 
-      error when is_atom(error) ->
-        {:error, error}
-
-      {:error, reason} ->
-        {:error, reason}
+      {:error, details} ->
+        nif_error(details, handle)
     end
   end
+  defp do_set(path, name, value, opts) do
+    mode = Keyword.get(opts, :mode, :upsert)
 
-  @doc """
-  Set an extended attribute on the specified file. Passing a `nil` value will remove the attribute, raises on error.
-  """
-  @spec set!(Path.t(), name(), value()) :: :ok
-  def set!(path, name, value) do
-    case set(path, name, value) do
-      {:error, reason} ->
-        raise Error,
-          action: "set xattr #{inspect name} -> #{inspect value} for",
-          path: path,
-          reason: reason
+    case Nif.set_xattr(path, name, to_string(value), follow_symlinks?(opts), mode) do
+      :ok ->
+        :ok
 
-      :ok -> :ok
+      {:error, details} ->
+        nif_error(details, path)
     end
   end
 
@@ -278,39 +567,46 @@ defmodule ExAttr do
   iex> ExAttr.get("test.txt", "user.foo")
   {:ok, nil}
   iex> ExAttr.remove("test.txt", "user.foo")
-  {:error, "No data available (os error 61)"}
+  {:error, :enodata}
   ```
   """
-  @spec remove(Path.t(), name()) :: result()
-  def remove(path, name) do
-    case Nif.remove_xattr(path, name) do
-      :ok ->
-        :ok
-
-      error when is_atom(error) ->
-        {:error, error}
-
-      {:error, reason} ->
-        {:error, reason}
-    end
+  @spec remove(Path.t() | Handle.t(), name(), options()) :: result()
+  def remove(path, name, opts \\ []) do
+    path |> do_remove(name, opts) |> to_reason()
   end
 
   @doc """
   Remove an extended attribute from the specified file, raises on error.
   """
-  @spec remove!(Path.t(), name()) :: :ok
-  def remove!(path, name) do
-    case remove(path, name) do
-      {:error, reason} ->
-        raise Error,
-          action: "remove xattr #{inspect name} from",
-          path: path,
-          reason: reason
+  @spec remove!(Path.t() | Handle.t(), name(), options()) :: :ok
+  def remove!(path, name, opts \\ []) do
+    case do_remove(path, name, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "remove xattr #{inspect name} from"}
 
       :ok -> :ok
     end
   end
 
+  defp do_remove(%Handle{ref: ref} = handle, name, _opts) do
+    case Nif.handle_remove_xattr(ref, name) do
+      :ok ->
+        :ok
+
+      {:error, details} ->
+        nif_error(details, handle)
+    end
+  end
+  defp do_remove(path, name, opts) do
+    case Nif.remove_xattr(path, name, follow_symlinks?(opts)) do
+      :ok ->
+        :ok
+
+      {:error, details} ->
+        nif_error(details, path)
+    end
+  end
+
   @doc """
   List extended attributes attached to the specified file.
 
@@ -324,16 +620,52 @@ defmodule ExAttr do
   iex> :ok = ExAttr.set("test.txt", "user.test", "example")
   iex> ExAttr.list("test.txt")
   {:ok, ["user.test", "user.bar", "user.foo"]}
+  iex> ExAttr.list("test.txt", prefix: "user.t")
+  {:ok, ["user.test"]}
+  iex> ExAttr.list("test.txt", prefix: "user.", strip_prefix: true)
+  {:ok, ["test", "bar", "foo"]}
+  iex> ExAttr.list("test.txt", prefix: "user.t", parse_names: true)
+  {:ok, [%ExAttr.Name{namespace: :user, key: "test"}]}
   ```
   """
-  @spec list(Path.t()) :: result(list(name()))
-  def list(path) do
-    case Nif.list_xattr(path) do
-      {:error, reason} ->
-        {:error, reason}
+  @spec list(Path.t() | Handle.t(), list_options()) :: result(list(name() | Name.t()))
+  def list(path, opts \\ []) do
+    path |> do_list(opts) |> to_reason()
+  end
+
+  @doc """
+  List extended attributes attached to the specified file, raises on error.
 
-      error when is_atom(error) ->
-        {:error, error}
+  > #### Note {: .info}
+  > This may not list all attributes. Speficially, it definitely won’t list any trusted attributes unless you are root and it may not list system attributes.
+  """
+  @spec list!(Path.t() | Handle.t(), list_options()) :: list(name() | Name.t())
+  def list!(path, opts \\ []) do
+    case do_list(path, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "list xattr for"}
+
+      {:ok, value} -> value
+    end
+  end
+
+  defp do_list(%Handle{ref: ref} = handle, opts) do
+    {prefix, strip, parse} = list_opts(opts)
+
+    case Nif.handle_list_xattr(ref, prefix, strip, parse) do
+      {:error, details} ->
+        nif_error(details, handle)
+
+      value ->
+        {:ok, value}
+    end
+  end
+  defp do_list(path, opts) do
+    {prefix, strip, parse} = list_opts(opts)
+
+    case Nif.list_xattr(path, follow_symlinks?(opts), prefix, strip, parse) do
+      {:error, details} ->
+        nif_error(details, path)
 
       value ->
         {:ok, value}
@@ -341,27 +673,101 @@ defmodule ExAttr do
   end
 
   @doc """
-  List extended attributes attached to the specified file, raises on error.
+  Get the size in bytes of an extended attribute's value without reading it, returns
+  `{:ok, nil}` if the attribute doesn't exist.
+
+  ## Examples
+  ```elixir
+  iex> ExAttr.size("test.txt", "user.foo")
+  {:ok, nil}
+  iex> ExAttr.set("test.txt", "user.foo", "123")
+  :ok
+  iex> ExAttr.size("test.txt", "user.foo")
+  {:ok, 3}
+  ```
+  """
+  @spec size(Path.t(), name(), options()) :: result(non_neg_integer() | nil)
+  def size(path, name, opts \\ []) do
+    path |> do_size(name, opts) |> to_reason()
+  end
+
+  @doc """
+  Get the size in bytes of an extended attribute's value without reading it, raises on error.
+  """
+  @spec size!(Path.t(), name(), options()) :: non_neg_integer() | nil
+  def size!(path, name, opts \\ []) do
+    case do_size(path, name, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "get size of xattr #{inspect name} from"}
+
+      {:ok, size} -> size
+    end
+  end
+
+  defp do_size(path, name, opts) do
+    case Nif.size_xattr(path, name, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      size ->
+        {:ok, size}
+    end
+  end
+
+  @doc """
+  List extended attributes attached to the specified file along with the size in bytes of
+  their values, in a single native call and without reading the values themselves.
 
   > #### Note {: .info}
   > This may not list all attributes. Speficially, it definitely won’t list any trusted attributes unless you are root and it may not list system attributes.
+
+  ## Examples
+  ```elixir
+  iex> :ok = ExAttr.set("test.txt", "user.foo", "bar")
+  iex> :ok = ExAttr.set("test.txt", "user.test", "example")
+  iex> ExAttr.list_with_sizes("test.txt")
+  {:ok, [{"user.test", 7}, {"user.foo", 3}]}
+  ```
+  """
+  @spec list_with_sizes(Path.t(), options()) :: result(list({name(), non_neg_integer()}))
+  def list_with_sizes(path, opts \\ []) do
+    path |> do_list_with_sizes(opts) |> to_reason()
+  end
+
+  @doc """
+  List extended attributes attached to the specified file along with the size in bytes of
+  their values, raises on error.
   """
-  @spec list!(Path.t()) :: list(name())
-  def list!(path) do
-    case Nif.list_xattr(path) do
-      {:error, reason} ->
-        raise Error,
-          action: "list xattr for",
-          path: path,
-          reason: reason
+  @spec list_with_sizes!(Path.t(), options()) :: list({name(), non_neg_integer()})
+  def list_with_sizes!(path, opts \\ []) do
+    case do_list_with_sizes(path, opts) do
+      {:error, error} ->
+        raise %Error{error | action: "list xattr sizes for"}
+
+      {:ok, sizes} -> sizes
+    end
+  end
+
+  defp do_list_with_sizes(path, opts) do
+    case Nif.list_sizes_xattr(path, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, path)
 
-      value -> value
+      sizes ->
+        {:ok, sizes}
     end
   end
 
   @doc """
   Dumps map of extended attributes for the specified file.
 
+  All names and values are read natively in a single call, through one open file
+  descriptor whenever the file can be opened. Attributes removed by another process
+  while the dump is in progress are skipped rather than causing an error.
+
+  Accepts the same `:prefix`, `:strip_prefix` and `:parse_names` options as `list/2`,
+  values of names that don't match the prefix are never read.
+
   > #### Note {: .info}
   > This may not list all attributes. Speficially, it definitely won’t list any trusted attributes unless you are root and it may not list system attributes.
 
@@ -375,18 +781,9 @@ defmodule ExAttr do
   {:ok, %{"user.bar" => "foo", "user.foo" => "bar", "user.test" => "example"}}
   ```
   """
-  @spec dump(Path.t()) :: result(%{name() => value()})
-  def dump(path) do
-    case list(path) do
-      {:ok, list} ->
-        # If we can list the attribute names then we should be able to get their values
-        {:ok, Map.new(list, fn name ->
-          {name, get!(path, name)}
-        end)}
-
-      {:error, reason} ->
-        {:error, reason}
-    end
+  @spec dump(Path.t() | Handle.t(), list_options()) :: result(%{(name() | Name.t()) => value()})
+  def dump(path, opts \\ []) do
+    path |> do_dump(opts) |> to_reason()
   end
 
   @doc """
@@ -395,16 +792,904 @@ defmodule ExAttr do
   > #### Note {: .info}
   > This may not list all attributes. Speficially, it definitely won’t list any trusted attributes unless you are root and it may not list system attributes.
   """
-  @spec dump!(Path.t()) :: %{name() => value()}
-  def dump!(path) do
-    case dump(path) do
+  @spec dump!(Path.t() | Handle.t(), list_options()) :: %{(name() | Name.t()) => value()}
+  def dump!(path, opts \\ []) do
+    case do_dump(path, opts) do
       {:ok, map} -> map
-      {:error, reason} ->
-        raise Error,
-          action: "dump xattr for",
-          path: path,
-          reason: reason
+      {:error, error} ->
+        raise %Error{error | action: "dump xattr for"}
+    end
+  end
+
+  defp do_dump(%Handle{ref: ref} = handle, opts) do
+    {prefix, strip, parse} = list_opts(opts)
+
+    case Nif.handle_dump_xattr(ref, prefix, strip, parse) do
+      {:error, details} ->
+        nif_error(details, handle)
+
+      map ->
+        {:ok, map}
+    end
+  end
+  defp do_dump(path, opts) do
+    {prefix, strip, parse} = list_opts(opts)
+
+    case Nif.dump_xattr(path, follow_symlinks?(opts), prefix, strip, parse) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      map ->
+        {:ok, map}
+    end
+  end
+
+  @doc """
+  Dumps the extended attributes of every file under the specified directory.
+
+  The tree is walked natively in a single call, depth first with each directory's entries
+  sorted by name, and the root itself is included. Only files that have attributes
+  (matching the `:prefix`, if given) are returned. Files removed while the walk is in
+  progress are skipped, any other error stops it and is raised with the path of the file
+  it happened on.
+
+  For large trees on fast storage, most of the time goes into waiting on syscalls. Pass
+  `:threads` to spread the walk over that many native threads, which send their results
+  back to the calling process in batches. Only a couple of batches are in flight at any
+  time, the threads pause until the caller has taken them in, and they stop as soon as
+  the caller exits.
+
+  ## Examples
+
+  ```elixir
+  :ok = ExAttr.set("media/a.mkv", "user.foo", "bar")
+  :ok = ExAttr.set("media/shows/b.mkv", "user.foo", "baz")
+  ExAttr.dump_tree("media")
+  #=> {:ok, [{"media/a.mkv", %{"user.foo" => "bar"}}, {"media/shows/b.mkv", %{"user.foo" => "baz"}}]}
+  ExAttr.dump_tree("media", max_depth: 1)
+  #=> {:ok, [{"media/a.mkv", %{"user.foo" => "bar"}}]}
+  {:ok, tree} = ExAttr.dump_tree("media", threads: 8)
+  Enum.sort(tree)
+  #=> [{"media/a.mkv", %{"user.foo" => "bar"}}, {"media/shows/b.mkv", %{"user.foo" => "baz"}}]
+  ```
+  """
+  @spec dump_tree(Path.t(), tree_options()) ::
+          result(list({Path.t(), %{(name() | Name.t()) => value()}}))
+  def dump_tree(path, opts \\ []) do
+    path |> do_dump_tree(opts) |> to_reason()
+  end
+
+  @doc """
+  Dumps the extended attributes of every file under the specified directory, raises on error.
+  """
+  @spec dump_tree!(Path.t(), tree_options()) ::
+          list({Path.t(), %{(name() | Name.t()) => value()}})
+  def dump_tree!(path, opts \\ []) do
+    case do_dump_tree(path, opts) do
+      {:ok, tree} -> tree
+      {:error, error} ->
+        raise %Error{error | action: "dump xattr tree for"}
+    end
+  end
+
+  defp do_dump_tree(path, opts) do
+    {prefix, strip, parse} = list_opts(opts)
+    {max_depth, cross_mounts} = tree_opts(opts)
+    follow = follow_symlinks?(opts)
+
+    result =
+      case Keyword.fetch(opts, :threads) do
+        {:ok, threads} ->
+          tag = make_ref()
+          batch_size = Keyword.get(opts, :batch_size, 1000)
+
+          case Nif.start_tree_scan(
+                 path, tag, follow, max_depth, cross_mounts, prefix, strip, parse, threads, batch_size
+               ) do
+            {:error, details} -> {:error, details}
+            scan -> receive_tree(scan, tag, [])
+          end
+
+        :error ->
+          Nif.dump_tree_xattr(path, follow, max_depth, cross_mounts, prefix, strip, parse)
+      end
+
+    case result do
+      {:error, details} ->
+        nif_error(details, path)
+
+      tree ->
+        {:ok, tree}
+    end
+  end
+
+  @doc """
+  Lazily streams the extended attributes of every file under the specified directory.
+
+  Takes the same options as `dump_tree/2` except `:threads`, and returns the same
+  `{path, attrs}` entries in the same order. The tree is walked natively a batch of
+  `:batch_size` files at a time, only when the stream asks for more, so memory use stays
+  flat no matter how big the tree is. Halting the stream early (ex: with `Enum.take/2`)
+  stops the walk and frees it straight away.
+
+  Since a stream can't return an error tuple, errors raise an `ExAttr.Error` when the
+  stream is run.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.stream_tree("media") |> Enum.take(1)
+  #=> [{"media/a.mkv", %{"user.foo" => "bar"}}]
+  ```
+  """
+  @spec stream_tree(Path.t(), tree_options()) ::
+          Enumerable.t({Path.t(), %{(name() | Name.t()) => value()}})
+  def stream_tree(path, opts \\ []) do
+    {prefix, strip, parse} = list_opts(opts)
+    {max_depth, cross_mounts} = tree_opts(opts)
+    follow = follow_symlinks?(opts)
+    batch_size = Keyword.get(opts, :batch_size, 1000)
+
+    Stream.resource(
+      fn ->
+        Nif.open_tree_stream(path, follow, max_depth, cross_mounts, prefix, strip, parse)
+      end,
+      fn stream ->
+        case Nif.next_tree_stream(stream, batch_size) do
+          [] ->
+            {:halt, stream}
+
+          {:error, details} ->
+            {:error, error} = nif_error(details, path)
+            raise %Error{error | action: "stream xattr tree for"}
+
+          batch ->
+            {batch, stream}
+        end
+      end,
+      &Nif.close_tree_stream/1
+    )
+  end
+
+  # Every batch has to be acknowledged before the scan sends more than a couple of them
+  defp receive_tree(scan, tag, batches) do
+    receive do
+      {^tag, {:batch, batch}} ->
+        :ok = Nif.ack_tree_scan(scan)
+        receive_tree(scan, tag, [batch | batches])
+
+      {^tag, :done} ->
+        batches |> Enum.reverse() |> Enum.concat()
+
+      {^tag, {:error, details}} ->
+        {:error, details}
     end
   end
 
+  @doc """
+  Finds every file under the specified directory whose attributes match a predicate.
+
+  The tree is walked and the predicate evaluated natively, only the paths of the matching
+  files are returned, in the same order as `dump_tree/2`. Only the attributes named by
+  the predicate are read. Takes the same `:max_depth`, `:cross_mounts` and
+  `:follow_symlinks` options as `dump_tree/2`.
+
+  Predicates are tuples naming the attribute they check:
+
+    * `{:exists, name}` / `{:missing, name}` - whether the attribute is set
+    * `{:equals, name, value}` - the value is exactly `value`
+    * `{:prefix, name, prefix}` - the value starts with `prefix`
+    * `{:regex, name, regex}` - the value matches `regex`, either a `Regex` or a pattern
+      string. Matching is done by Rust's [`regex`](https://docs.rs/regex) crate, so
+      lookarounds and backreferences aren't supported. Invalid patterns raise an
+      `ArgumentError`.
+    * `{:size, name, op, size}` - the size of the value compared with `op` (`:<`, `:<=`,
+      `:==`, `:!=`, `:>=` or `:>`) to `size`
+    * `{:and, predicates}`, `{:or, predicates}` and `{:not, predicate}` to combine them
+
+  Every predicate other than `:missing` is false for files without the attribute.
+
+  ## Examples
+
+  ```elixir
+  :ok = ExAttr.set("data/a.txt", "user.myapp.status", "pending")
+  :ok = ExAttr.set("data/b.txt", "user.myapp.status", "done")
+  ExAttr.find("data", {:equals, "user.myapp.status", "pending"})
+  #=> {:ok, ["data/a.txt"]}
+  ExAttr.find("data", {:and, [{:exists, "user.myapp.status"}, {:missing, "user.checksum"}]})
+  #=> {:ok, ["data/a.txt", "data/b.txt"]}
+  ```
+  """
+  @spec find(Path.t(), predicate(), tree_options()) :: result(list(Path.t()))
+  def find(path, predicate, opts \\ []) do
+    path |> do_find(predicate, opts) |> to_reason()
+  end
+
+  @doc """
+  Finds every file under the specified directory whose attributes match a predicate,
+  raises on error.
+  """
+  @spec find!(Path.t(), predicate(), tree_options()) :: list(Path.t())
+  def find!(path, predicate, opts \\ []) do
+    case do_find(path, predicate, opts) do
+      {:ok, paths} -> paths
+      {:error, error} ->
+        raise %Error{error | action: "find xattr under"}
+    end
+  end
+
+  defp do_find(path, predicate, opts) do
+    {max_depth, cross_mounts} = tree_opts(opts)
+    predicate = native_predicate(predicate)
+
+    case Nif.find_xattr(path, predicate, follow_symlinks?(opts), max_depth, cross_mounts) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      paths ->
+        {:ok, paths}
+    end
+  end
+
+  @regex_flags %{caseless: "i", multiline: "m", dotall: "s", extended: "x", ungreedy: "U"}
+
+  # Compiled regexes are handed over as their source, with the options Rust's regex
+  # syntax has an inline flag for
+  defp native_predicate({:regex, name, %Regex{} = regex}) do
+    flags = regex |> Regex.opts() |> Enum.map(&Map.get(@regex_flags, &1, "")) |> Enum.join()
+    pattern = if flags == "", do: Regex.source(regex), else: "(?#{flags})" <> Regex.source(regex)
+    {:regex, name, pattern}
+  end
+  defp native_predicate({op, predicates}) when op in [:and, :or] and is_list(predicates),
+    do: {op, Enum.map(predicates, &native_predicate/1)}
+  defp native_predicate({:not, predicate}), do: {:not, native_predicate(predicate)}
+  defp native_predicate(predicate), do: predicate
+
+  @doc """
+  Copies the extended attributes of one file onto another.
+
+  The source is read and every selected attribute set on the destination in a single
+  native call. Attributes that fail to be set or removed (ex: `security.*` without the
+  privilege for it) are reported under `:failed` along with their reason rather than
+  stopping the copy, only failing to read either file at all returns an error.
+
+  ## Examples
+
+  ```elixir
+  :ok = ExAttr.set("a.txt", "user.foo", "bar")
+  :ok = ExAttr.set("b.txt", "user.old", "1")
+  ExAttr.copy("a.txt", "b.txt", namespaces: [:user], remove_extra: true)
+  #=> {:ok, %{copied: ["user.foo"], skipped: [], removed: ["user.old"], failed: []}}
+  ```
+  """
+  @spec copy(Path.t(), Path.t(), copy_options()) :: result(copy_report())
+  def copy(src, dst, opts \\ []) do
+    src |> do_copy(dst, opts) |> to_reason()
+  end
+
+  @doc """
+  Copies the extended attributes of one file onto another, raises if either file can't
+  be read. Failures of single attributes are still only reported.
+  """
+  @spec copy!(Path.t(), Path.t(), copy_options()) :: copy_report()
+  def copy!(src, dst, opts \\ []) do
+    case do_copy(src, dst, opts) do
+      {:ok, report} -> report
+      {:error, error} ->
+        raise %Error{error | action: "copy xattr from"}
+    end
+  end
+
+  defp do_copy(src, dst, opts) do
+    namespaces = Keyword.get(opts, :namespaces)
+    prefix = Keyword.get(opts, :prefix)
+    conflict = Keyword.get(opts, :on_conflict, :overwrite)
+    remove_extra = Keyword.get(opts, :remove_extra, false)
+    follow = follow_symlinks?(opts)
+
+    case Nif.copy_xattr(src, dst, follow, namespaces, prefix, conflict, remove_extra) do
+      {:error, details} ->
+        nif_error(details, src)
+
+      report ->
+        {:ok, report}
+    end
+  end
+
+  @doc """
+  Compares the extended attributes of two files.
+
+  Either side can be a path or a map of names to values, such as a previous `dump/2` of
+  the file, so a file can be compared to a snapshot of itself. Both sides are read and
+  compared in a single native call. The diff lists what it takes to turn the left side
+  into the right one:
+
+    * `:added` - attributes only the right side has, with their value
+    * `:removed` - attributes only the left side has, with their value
+    * `:changed` - attributes whose value differs, with their `{old, new}` values
+
+  Pass it to `patch/3` to apply it to a file.
+
+  ## Examples
+
+  ```elixir
+  snapshot = ExAttr.dump!("a.txt")
+  :ok = ExAttr.set("a.txt", "user.foo", "baz")
+  :ok = ExAttr.set("a.txt", "user.new", "1")
+  ExAttr.diff(snapshot, "a.txt")
+  #=> {:ok, %{added: %{"user.new" => "1"}, removed: %{}, changed: %{"user.foo" => {"bar", "baz"}}}}
+  ```
+  """
+  @spec diff(diff_side(), diff_side(), diff_options()) :: result(diff())
+  def diff(left, right, opts \\ []) do
+    left |> do_diff(right, opts) |> to_reason()
+  end
+
+  @doc """
+  Compares the extended attributes of two files, raises on error.
+  """
+  @spec diff!(diff_side(), diff_side(), diff_options()) :: diff()
+  def diff!(left, right, opts \\ []) do
+    case do_diff(left, right, opts) do
+      {:ok, diff} -> diff
+      {:error, error} ->
+        raise %Error{error | action: "diff xattr of"}
+    end
+  end
+
+  defp do_diff(left, right, opts) do
+    prefix = Keyword.get(opts, :prefix)
+
+    # Errors reading either side carry their path, anything else isn't about a file
+    case Nif.diff_xattr(left, right, follow_symlinks?(opts), prefix) do
+      {:error, details} ->
+        nif_error(details, nil)
+
+      diff ->
+        {:ok, diff}
+    end
+  end
+
+  @doc """
+  Applies a diff returned by `diff/3` to a file.
+
+  Removed attributes are removed, then added and changed ones are set to their new value,
+  all in a single native call. The old values of a diff aren't checked against the file.
+  Any of the `:added`, `:removed` and `:changed` keys can be left out. The patch stops at
+  the first attribute that fails, the error carries its name.
+
+  ## Examples
+
+  ```elixir
+  {:ok, diff} = ExAttr.diff("b.txt", "a.txt")
+  ExAttr.patch("b.txt", diff)
+  #=> :ok
+  ExAttr.diff("b.txt", "a.txt")
+  #=> {:ok, %{added: %{}, removed: %{}, changed: %{}}}
+  ```
+  """
+  @spec patch(Path.t(), diff(), options()) :: result()
+  def patch(path, diff, opts \\ []) do
+    path |> do_patch(diff, opts) |> to_reason()
+  end
+
+  @doc """
+  Applies a diff returned by `diff/3` to a file, raises on error.
+  """
+  @spec patch!(Path.t(), diff(), options()) :: :ok
+  def patch!(path, diff, opts \\ []) do
+    case do_patch(path, diff, opts) do
+      :ok -> :ok
+      {:error, error} ->
+        raise %Error{error | action: "patch xattr of"}
+    end
+  end
+
+  defp do_patch(path, diff, opts) do
+    case Nif.patch_xattr(path, diff, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      :ok ->
+        :ok
+    end
+  end
+
+  @doc """
+  Exports the extended attributes of every file under the specified path in the format
+  of `getfattr --dump`.
+
+  The output is the same as `getfattr -d -m - -R` would give, so it can be restored with
+  `setfattr --restore` or `import/2`. Every file with attributes gets a `# file:` header
+  followed by a `name=value` line per attribute, values being written as `"text"`, `0x`
+  hex or `0s` base64. Paths are written as walked, relative ones stay relative. Unlike
+  getfattr, absolute paths keep their leading `/` and a trailing NUL byte in a text value
+  is kept, so values always round-trip exactly.
+
+  The tree is walked and formatted natively in the same order as `dump_tree/2`, pass
+  `max_depth: 0` to only export the path itself.
+
+  ## Examples
+
+  ```elixir
+  :ok = ExAttr.set("data/a.txt", "user.foo", "bar")
+  :ok = ExAttr.set("data/a.txt", "user.bin", <<0, 1, 2>>)
+  ExAttr.export("data")
+  #=> {:ok, "# file: data/a.txt\nuser.bin=0sAAEC\nuser.foo=\"bar\"\n\n"}
+  ```
+  """
+  @spec export(Path.t(), export_options()) :: result(binary())
+  def export(path, opts \\ []) do
+    path |> do_export(opts) |> to_reason()
+  end
+
+  @doc """
+  Exports the extended attributes of every file under the specified path in the format
+  of `getfattr --dump`, raises on error.
+  """
+  @spec export!(Path.t(), export_options()) :: binary()
+  def export!(path, opts \\ []) do
+    case do_export(path, opts) do
+      {:ok, dump} -> dump
+      {:error, error} ->
+        raise %Error{error | action: "export xattr for"}
+    end
+  end
+
+  defp do_export(path, opts) do
+    {max_depth, cross_mounts} = tree_opts(opts)
+    prefix = Keyword.get(opts, :prefix)
+    encoding = Keyword.get(opts, :encoding)
+    follow = follow_symlinks?(opts)
+
+    case Nif.export_xattr(path, follow, max_depth, cross_mounts, prefix, encoding) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      dump ->
+        {:ok, dump}
+    end
+  end
+
+  @doc """
+  Imports a dump in the format of `getfattr --dump`, setting every attribute it lists.
+
+  Takes the output of `export/2` or `getfattr -d`, and works like `setfattr --restore`.
+  Relative paths are resolved against the current working directory. Attributes the files
+  have but the dump doesn't list are left alone.
+
+  The whole dump is parsed and every name and value validated natively before any
+  attribute is set, so a malformed dump fails with `{:invalid_dump, line}` without
+  changing anything. Failing to set an attribute stops the import there.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.import("# file: data/a.txt\nuser.foo=\"bar\"\n\n")
+  #=> :ok
+  ExAttr.get("data/a.txt", "user.foo")
+  #=> {:ok, "bar"}
+  ```
+  """
+  @spec import(binary(), options()) :: result()
+  def import(dump, opts \\ []) do
+    dump |> do_import(opts) |> to_reason()
+  end
+
+  @doc """
+  Imports a dump in the format of `getfattr --dump`, raises on error.
+  """
+  @spec import!(binary(), options()) :: :ok
+  def import!(dump, opts \\ []) do
+    case do_import(dump, opts) do
+      :ok -> :ok
+      {:error, error} ->
+        raise %Error{error | action: "import xattr dump into"}
+    end
+  end
+
+  # Errors setting an attribute carry the path of their file
+  defp do_import(dump, opts) do
+    case Nif.import_xattr(dump, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, nil)
+
+      :ok ->
+        :ok
+    end
+  end
+
+  @doc """
+  Takes a snapshot of the extended attributes of every file under the specified path,
+  serialized as JSON or CBOR.
+
+  The tree is walked and serialized natively in the same order as `dump_tree/2`, pass
+  `max_depth: 0` to only snapshot the path itself. Both formats follow the same schema,
+  a `version` (currently `1`) and the list of files with attributes:
+
+  ```json
+  {
+    "version": 1,
+    "files": [
+      {"path": "data/a.txt", "xattrs": [{"name": "user.foo", "value": "YmFy"}]}
+    ]
+  }
+  ```
+
+  In JSON values are base64 strings. Paths and names are strings as well, and those that
+  aren't valid UTF-8 are given as a base64 `"path_base64"` or `"name_base64"` in place of
+  `"path"` or `"name"`. In CBOR paths, names and values are all byte strings, while the
+  keys stay text strings.
+
+  ## Examples
+
+  ```elixir
+  :ok = ExAttr.set("data/a.txt", "user.foo", "bar")
+  ExAttr.snapshot("data")
+  #=> {:ok, ~s({"files":[{"path":"data/a.txt","xattrs":[{"name":"user.foo","value":"YmFy"}]}],"version":1})}
+  ```
+  """
+  @spec snapshot(Path.t(), snapshot_options()) :: result(binary())
+  def snapshot(path, opts \\ []) do
+    path |> do_snapshot(opts) |> to_reason()
+  end
+
+  @doc """
+  Takes a snapshot of the extended attributes of every file under the specified path,
+  raises on error.
+  """
+  @spec snapshot!(Path.t(), snapshot_options()) :: binary()
+  def snapshot!(path, opts \\ []) do
+    case do_snapshot(path, opts) do
+      {:ok, snapshot} -> snapshot
+      {:error, error} ->
+        raise %Error{error | action: "snapshot xattr for"}
+    end
+  end
+
+  defp do_snapshot(path, opts) do
+    {max_depth, cross_mounts} = tree_opts(opts)
+    format = Keyword.get(opts, :format, :json)
+    prefix = Keyword.get(opts, :prefix)
+    follow = follow_symlinks?(opts)
+
+    case Nif.snapshot_xattr(path, format, follow, max_depth, cross_mounts, prefix) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      snapshot ->
+        {:ok, snapshot}
+    end
+  end
+
+  @doc """
+  Restores a snapshot taken by `snapshot/2`, setting every attribute it lists.
+
+  Works the same as `import/2`: the whole snapshot is decoded and validated natively
+  before any attribute is set, relative paths are resolved against the current working
+  directory and attributes the files have but the snapshot doesn't list are left alone.
+
+  ## Examples
+
+  ```elixir
+  {:ok, snapshot} = ExAttr.snapshot("data", format: :cbor)
+  ExAttr.restore(snapshot, format: :cbor)
+  #=> :ok
+  ```
+  """
+  @spec restore(binary(), restore_options()) :: result()
+  def restore(snapshot, opts \\ []) do
+    snapshot |> do_restore(opts) |> to_reason()
+  end
+
+  @doc """
+  Restores a snapshot taken by `snapshot/2`, raises on error.
+  """
+  @spec restore!(binary(), restore_options()) :: :ok
+  def restore!(snapshot, opts \\ []) do
+    case do_restore(snapshot, opts) do
+      :ok -> :ok
+      {:error, error} ->
+        raise %Error{error | action: "restore xattr snapshot into"}
+    end
+  end
+
+  # Errors setting an attribute carry the path of their file
+  defp do_restore(snapshot, opts) do
+    format = Keyword.get(opts, :format, :json)
+
+    case Nif.restore_xattr(snapshot, format, follow_symlinks?(opts)) do
+      {:error, details} ->
+        nif_error(details, nil)
+
+      :ok ->
+        :ok
+    end
+  end
+
+  @doc """
+  Lists the extended attributes recorded in a tar archive.
+
+  GNU tar and bsdtar store attributes as `SCHILY.xattr.<name>` records of PAX headers,
+  which are read natively along with the entry they describe. Only entries that have
+  attributes are returned, in archive order. Only `user.*` attributes are listed by
+  default, see `t:tar_option/0`. Archives with entries whose size isn't the one in their
+  own header (ex: PAX `size` records or GNU sparse files) return an error rather than
+  attributes that might belong to another entry.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.list_tar("media.tar")
+  #=> {:ok, [{"media/a.mkv", %{"user.foo" => "bar"}}]}
+  ```
+  """
+  @spec list_tar(Path.t(), [tar_option()]) :: result(list({Path.t(), %{name() => value()}}))
+  def list_tar(archive, opts \\ []) do
+    archive |> do_list_tar(opts) |> to_reason()
+  end
+
+  @doc """
+  Lists the extended attributes recorded in a tar archive, raises on error.
+  """
+  @spec list_tar!(Path.t(), [tar_option()]) :: list({Path.t(), %{name() => value()}})
+  def list_tar!(archive, opts \\ []) do
+    case do_list_tar(archive, opts) do
+      {:ok, entries} -> entries
+      {:error, error} ->
+        raise %Error{error | action: "list xattr in tar"}
+    end
+  end
+
+  defp do_list_tar(archive, opts) do
+    case Nif.list_tar_xattr(archive, Keyword.get(opts, :prefix, "user.")) do
+      {:error, details} ->
+        nif_error(details, archive)
+
+      entries ->
+        {:ok, entries}
+    end
+  end
+
+  @doc """
+  Extracts a tar archive into the `dest` directory, setting the extended attributes it
+  recorded on the extracted files.
+
+  Entries are unpacked natively and each gets its attributes set right after, entries
+  with a `..` component are skipped. Directories are unpacked last, deepest first, so
+  that their permissions don't get in the way of what goes in them. Read-only files and
+  directories are made writable while their attributes are set. Only `user.*` attributes
+  are set by default, since setting `security.*` or `trusted.*` ones usually takes
+  privileges, see `t:tar_option/0`.
+
+  Every record is checked before anything is unpacked. Attributes that are invalid or
+  fail to be set (ex: `trusted.*` ones without the privilege for it) don't stop the
+  extraction, they are reported under `:failed` along with the extracted file they belong
+  to. Only failing to read the archive or to unpack an entry returns an error.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.extract_tar("media.tar", "restored")
+  #=> {:ok, %{failed: []}}
+  ExAttr.get("restored/media/a.mkv", "user.foo")
+  #=> {:ok, "bar"}
+  ```
+  """
+  @spec extract_tar(Path.t(), Path.t(), [tar_option()]) :: result(extract_tar_report())
+  def extract_tar(archive, dest, opts \\ []) do
+    archive |> do_extract_tar(dest, opts) |> to_reason()
+  end
+
+  @doc """
+  Extracts a tar archive into the `dest` directory along with its extended attributes,
+  raises on error. Attributes that fail to be set are still only reported.
+  """
+  @spec extract_tar!(Path.t(), Path.t(), [tar_option()]) :: extract_tar_report()
+  def extract_tar!(archive, dest, opts \\ []) do
+    case do_extract_tar(archive, dest, opts) do
+      {:ok, report} -> report
+      {:error, error} ->
+        raise %Error{error | action: "extract tar"}
+    end
+  end
+
+  # Errors unpacking or setting attributes carry the path of the extracted file
+  defp do_extract_tar(archive, dest, opts) do
+    case Nif.extract_tar_xattr(archive, dest, Keyword.get(opts, :prefix, "user.")) do
+      {:error, details} ->
+        nif_error(details, archive)
+
+      report ->
+        {:ok, report}
+    end
+  end
+
+  @doc """
+  Creates a tar archive of the specified path, recording the extended attributes of
+  every file as `SCHILY.xattr` PAX records.
+
+  The tree is walked and archived natively in the same order as `dump_tree/2`. Entries
+  are named relative to the parent of `path`, the same as `tar -cf archive path` would,
+  and the archive can be extracted with its attributes by `extract_tar/3`,
+  `tar --xattrs -xf` or bsdtar. Only `user.*` attributes are archived by default.
+
+  The archive is built in a temporary file next to `archive` and only replaces it once
+  complete, so an existing archive is left as it was on error. When `archive` is inside
+  `path`, it is left out of itself.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.create_tar("media.tar", "media")
+  #=> :ok
+  ExAttr.list_tar("media.tar")
+  #=> {:ok, [{"media/a.mkv", %{"user.foo" => "bar"}}]}
+  ```
+  """
+  @spec create_tar(Path.t(), Path.t(), create_tar_options()) :: result()
+  def create_tar(archive, path, opts \\ []) do
+    archive |> do_create_tar(path, opts) |> to_reason()
+  end
+
+  @doc """
+  Creates a tar archive of the specified path along with its extended attributes, raises
+  on error.
+  """
+  @spec create_tar!(Path.t(), Path.t(), create_tar_options()) :: :ok
+  def create_tar!(archive, path, opts \\ []) do
+    case do_create_tar(archive, path, opts) do
+      :ok -> :ok
+      {:error, error} ->
+        raise %Error{error | action: "create tar"}
+    end
+  end
+
+  defp do_create_tar(archive, path, opts) do
+    {max_depth, cross_mounts} = tree_opts(opts)
+    prefix = Keyword.get(opts, :prefix, "user.")
+    follow = follow_symlinks?(opts)
+
+    case Nif.create_tar_xattr(archive, path, follow, max_depth, cross_mounts, prefix) do
+      {:error, details} ->
+        nif_error(details, archive)
+
+      :ok ->
+        :ok
+    end
+  end
+
+  @doc """
+  Copies a file along with its extended attributes, unlike `File.cp/2` which drops them.
+
+  The data is copied natively onto a temporary file next to the destination, through a
+  reflink when the filesystem supports it or `copy_file_range(2)` otherwise. The selected
+  attributes and the source's permissions are set on it, then it is synced and renamed
+  over the destination. The destination therefore appears complete with its attributes
+  or is left untouched, symlinks in `src` are always followed.
+
+  Returns the same report as `copy/3`, where `:skipped` and `:removed` are always empty.
+
+  ## Examples
+
+  ```elixir
+  ExAttr.cp("a.mkv", "b.mkv", namespaces: [:user])
+  #=> {:ok, %{copied: ["user.foo"], skipped: [], removed: [], failed: []}}
+  ExAttr.get("b.mkv", "user.foo")
+  #=> {:ok, "bar"}
+  ```
+  """
+  @spec cp(Path.t(), Path.t(), cp_options()) :: result(copy_report())
+  def cp(src, dst, opts \\ []) do
+    src |> do_cp(dst, opts) |> to_reason()
+  end
+
+  @doc """
+  Copies a file along with its extended attributes, raises on error.
+  """
+  @spec cp!(Path.t(), Path.t(), cp_options()) :: copy_report()
+  def cp!(src, dst, opts \\ []) do
+    case do_cp(src, dst, opts) do
+      {:ok, report} -> report
+      {:error, error} ->
+        raise %Error{error | action: "copy"}
+    end
+  end
+
+  # Errors carry the path of the file they happened on, source or destination
+  defp do_cp(src, dst, opts) do
+    namespaces = Keyword.get(opts, :namespaces)
+    prefix = Keyword.get(opts, :prefix)
+    on_error = Keyword.get(opts, :on_error, :fail)
+
+    case Nif.cp_xattr(src, dst, namespaces, prefix, on_error) do
+      {:error, details} ->
+        nif_error(details, src)
+
+      report ->
+        {:ok, report}
+    end
+  end
+
+  @doc """
+  Writes `contents` to a file along with the given extended attributes, atomically.
+
+  The contents are written natively to an `O_TMPFILE` file in the destination's
+  directory, or to a temporary name where those aren't supported, and every attribute is
+  set on it. It is then synced and linked or renamed into place, replacing any existing
+  file. The file therefore appears with all of its attributes or not at all, and is left
+  untouched if anything fails.
+
+  A file that gets replaced keeps its permissions, its owner and its other attributes
+  are not carried over. A new file gets the usual `0o666` permissions minus the umask.
+
+  ## Examples
+
+  ```elixir
+  pdf = "%PDF-1.7"
+  ExAttr.write("report.pdf", pdf, %{"user.checksum" => "d41d8cd9"})
+  #=> :ok
+  ExAttr.get("report.pdf", "user.checksum")
+  #=> {:ok, "d41d8cd9"}
+  ```
+  """
+  @spec write(Path.t(), iodata(), %{name() => binary()}) :: result()
+  def write(path, contents, attrs) do
+    path |> do_write(contents, attrs) |> to_reason()
+  end
+
+  @doc """
+  Writes `contents` to a file along with the given extended attributes, atomically,
+  raises on error.
+  """
+  @spec write!(Path.t(), iodata(), %{name() => binary()}) :: :ok
+  def write!(path, contents, attrs) do
+    case do_write(path, contents, attrs) do
+      :ok -> :ok
+      {:error, error} ->
+        raise %Error{error | action: "write"}
+    end
+  end
+
+  defp do_write(path, contents, attrs) do
+    case Nif.write_xattr(path, IO.iodata_to_binary(contents), attrs) do
+      {:error, details} ->
+        nif_error(details, path)
+
+      :ok ->
+        :ok
+    end
+  end
+
+  ###############
+  #   Helpers   #
+  ###############
+
+  defp follow_symlinks?(opts), do: Keyword.get(opts, :follow_symlinks, false)
+
+  defp list_opts(opts) do
+    strip = Keyword.get(opts, :strip_prefix, false)
+    parse = Keyword.get(opts, :parse_names, false)
+
+    # Stripped names have lost their namespace, there is nothing left to parse
+    if strip and parse do
+      raise ArgumentError, "the :strip_prefix and :parse_names options can't be combined"
+    end
+
+    {Keyword.get(opts, :prefix), strip, parse}
+  end
+
+  defp tree_opts(opts) do
+    {Keyword.get(opts, :max_depth), Keyword.get(opts, :cross_mounts, true)}
+  end
+
+  # The NIF reports failures as a map of the posix reason, raw errno, failing syscall and
+  # attribute name, bang functions raise all of it while the rest only return the reason.
+  # Tree walks also report the path they failed on, which is more precise than the root.
+  defp nif_error(details, %Handle{path: path}), do: nif_error(details, path)
+  defp nif_error(details, path), do: {:error, struct!(Error, Map.put_new(details, :path, path))}
+
+  defp to_reason({:error, %Error{reason: reason}}), do: {:error, reason}
+  defp to_reason(result), do: result
+
 end
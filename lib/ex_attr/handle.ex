@@ -0,0 +1,74 @@
+defmodule ExAttr.Handle do
+  @moduledoc """
+  An open file that extended attributes can be managed through repeatedly.
+
+  Every function in `ExAttr` resolves the path it is given on each call, which adds up
+  when managing dozens of attributes on one file and is racy if the file gets renamed or
+  replaced in between. A handle resolves the path once and keeps an open file descriptor,
+  every operation after that goes straight to the same file.
+
+  Handles can be passed to `ExAttr.get/3`, `ExAttr.set/4`, `ExAttr.remove/3`,
+  `ExAttr.list/2` and `ExAttr.dump/2` (and their bang variants) in place of a path. The
+  `:follow_symlinks` option only applies when opening, it is ignored afterwards. The file
+  descriptor is closed once the handle is garbage collected.
+
+  ## Examples
+  ```elixir
+  iex> {:ok, handle} = ExAttr.Handle.open("test.txt")
+  iex> ExAttr.set(handle, "user.foo", "123")
+  :ok
+  iex> ExAttr.get(handle, "user.foo")
+  {:ok, "123"}
+  ```
+  """
+  alias ExAttr.Nif
+
+  @enforce_keys [:ref, :path]
+  defstruct [:ref, :path]
+
+  @type t :: %__MODULE__{ref: reference(), path: Path.t()}
+
+  @doc """
+  Opens a handle to the specified file.
+
+  Since the f*xattr syscalls need a real file descriptor the file is opened read-only,
+  so read permission on it is required. When not following symlinks, opening a symlink
+  fails with `:eloop`.
+  """
+  @spec open(Path.t(), ExAttr.options()) :: ExAttr.result(t())
+  def open(path, opts \\ []) do
+    case do_open(path, opts) do
+      {:error, details} -> {:error, details.reason}
+      {:ok, handle} -> {:ok, handle}
+    end
+  end
+
+  @doc """
+  Opens a handle to the specified file, raises on error.
+  """
+  @spec open!(Path.t(), ExAttr.options()) :: t()
+  def open!(path, opts \\ []) do
+    case do_open(path, opts) do
+      {:error, details} ->
+        raise struct!(ExAttr.Error, Map.merge(details, %{action: "open", path: path}))
+
+      {:ok, handle} -> handle
+    end
+  end
+
+  defp do_open(path, opts) do
+    case Nif.open_handle(path, Keyword.get(opts, :follow_symlinks, false)) do
+      {:error, details} ->
+        {:error, details}
+
+      ref ->
+        {:ok, %__MODULE__{ref: ref, path: path}}
+    end
+  end
+
+  defimpl Inspect do
+    def inspect(%{path: path}, _opts) do
+      "#ExAttr.Handle<#{inspect(path)}>"
+    end
+  end
+end
@@ -0,0 +1,64 @@
+defmodule ExAttr.Name do
+  @moduledoc """
+  An attribute name split into its namespace and key.
+
+  Attribute names are stored as a single binary such as `"user.myapp.status"`, where
+  everything up to the first `.` is the namespace. Parsing and formatting are done
+  natively, using the same rules `ExAttr` validates names with, so a name that parses
+  is always one the other functions accept.
+
+  `ExAttr.list/2` and `ExAttr.dump/2` return names in this form when given
+  `parse_names: true`, which makes it easy to group attributes by namespace. The
+  `namespace` is `nil` on macOS, where names have no namespaces and the key is the
+  whole name.
+
+  ## Examples
+  ```elixir
+  iex> ExAttr.Name.parse("user.myapp.status")
+  {:ok, %ExAttr.Name{namespace: :user, key: "myapp.status"}}
+  iex> ExAttr.Name.format(%ExAttr.Name{namespace: :trusted, key: "foo"})
+  {:ok, "trusted.foo"}
+  iex> ExAttr.Name.parse("foo")
+  {:error, {:invalid_name, :no_namespace}}
+  ```
+  """
+  alias ExAttr.Nif
+
+  @enforce_keys [:namespace, :key]
+  defstruct [:namespace, :key]
+
+  @type namespace :: :user | :trusted | :security | :system | nil
+
+  @type t :: %__MODULE__{namespace: namespace(), key: binary()}
+
+  @doc """
+  Parses a raw attribute name.
+  """
+  @spec parse(ExAttr.name()) :: ExAttr.result(t())
+  def parse(name) do
+    case Nif.parse_name(name) do
+      {:error, details} -> {:error, details.reason}
+      name -> {:ok, name}
+    end
+  end
+
+  @doc """
+  Formats a name back into the raw binary stored on the file.
+  """
+  @spec format(t()) :: ExAttr.result(ExAttr.name())
+  def format(%__MODULE__{} = name) do
+    case Nif.format_name(name) do
+      {:error, details} -> {:error, details.reason}
+      name -> {:ok, name}
+    end
+  end
+
+  defimpl String.Chars do
+    def to_string(name) do
+      case ExAttr.Name.format(name) do
+        {:ok, name} -> name
+        {:error, reason} -> raise ArgumentError, ExAttr.Error.format(reason)
+      end
+    end
+  end
+end
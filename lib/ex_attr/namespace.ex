@@ -0,0 +1,106 @@
+defmodule ExAttr.Namespace do
+  @moduledoc """
+  Generates an `ExAttr` wrapper that owns a single attribute namespace.
+
+  Every name given to the generated functions is prefixed with the namespace, and names
+  are returned relative to it. Listing and dumping filter and strip the namespace
+  natively, so attributes outside of it are never copied over or even read.
+
+  ## Examples
+  ```elixir
+  defmodule MyApp.XAttr do
+    use ExAttr.Namespace, prefix: "user.myapp"
+  end
+
+  MyApp.XAttr.set("test.txt", "status", "pending")
+  # => sets "user.myapp.status"
+
+  MyApp.XAttr.dump("test.txt")
+  # => {:ok, %{"status" => "pending"}}
+  ```
+
+  The generated functions are `get`, `set`, `remove`, `list` and `dump` along with
+  their bang variants, accepting the same arguments and options as their `ExAttr`
+  counterparts (handles included). They are all overridable. `namespace/0` returns the
+  full prefix names are stored under.
+
+  ## Options
+    * `:prefix` (required) - the namespace, without a trailing `.` (ex: `"user.myapp"`)
+  """
+
+  defmacro __using__(opts) do
+    prefix = Keyword.fetch!(opts, :prefix)
+
+    quote bind_quoted: [prefix: prefix] do
+      @ex_attr_namespace prefix <> "."
+
+      @doc "The prefix every attribute managed by this module is stored under."
+      @spec namespace() :: ExAttr.name()
+      def namespace, do: @ex_attr_namespace
+
+      @doc "See `ExAttr.get/3`."
+      @spec get(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.options()) ::
+              ExAttr.result(ExAttr.value())
+      def get(path, name, opts \\ []),
+        do: ExAttr.get(path, @ex_attr_namespace <> name, opts)
+
+      @doc "See `ExAttr.get!/3`."
+      @spec get!(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.options()) ::
+              ExAttr.value()
+      def get!(path, name, opts \\ []),
+        do: ExAttr.get!(path, @ex_attr_namespace <> name, opts)
+
+      @doc "See `ExAttr.set/4`."
+      @spec set(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.value(), ExAttr.set_options()) ::
+              ExAttr.result()
+      def set(path, name, value, opts \\ []),
+        do: ExAttr.set(path, @ex_attr_namespace <> name, value, opts)
+
+      @doc "See `ExAttr.set!/4`."
+      @spec set!(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.value(), ExAttr.set_options()) ::
+              :ok
+      def set!(path, name, value, opts \\ []),
+        do: ExAttr.set!(path, @ex_attr_namespace <> name, value, opts)
+
+      @doc "See `ExAttr.remove/3`."
+      @spec remove(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.options()) ::
+              ExAttr.result()
+      def remove(path, name, opts \\ []),
+        do: ExAttr.remove(path, @ex_attr_namespace <> name, opts)
+
+      @doc "See `ExAttr.remove!/3`."
+      @spec remove!(Path.t() | ExAttr.Handle.t(), ExAttr.name(), ExAttr.options()) :: :ok
+      def remove!(path, name, opts \\ []),
+        do: ExAttr.remove!(path, @ex_attr_namespace <> name, opts)
+
+      @doc "See `ExAttr.list/2`."
+      @spec list(Path.t() | ExAttr.Handle.t(), ExAttr.options()) ::
+              ExAttr.result(list(ExAttr.name()))
+      def list(path, opts \\ []),
+        do: ExAttr.list(path, namespaced(opts))
+
+      @doc "See `ExAttr.list!/2`."
+      @spec list!(Path.t() | ExAttr.Handle.t(), ExAttr.options()) :: list(ExAttr.name())
+      def list!(path, opts \\ []),
+        do: ExAttr.list!(path, namespaced(opts))
+
+      @doc "See `ExAttr.dump/2`."
+      @spec dump(Path.t() | ExAttr.Handle.t(), ExAttr.options()) ::
+              ExAttr.result(%{ExAttr.name() => ExAttr.value()})
+      def dump(path, opts \\ []),
+        do: ExAttr.dump(path, namespaced(opts))
+
+      @doc "See `ExAttr.dump!/2`."
+      @spec dump!(Path.t() | ExAttr.Handle.t(), ExAttr.options()) ::
+              %{ExAttr.name() => ExAttr.value()}
+      def dump!(path, opts \\ []),
+        do: ExAttr.dump!(path, namespaced(opts))
+
+      defp namespaced(opts),
+        do: Keyword.merge(opts, prefix: @ex_attr_namespace, strip_prefix: true)
+
+      defoverridable get: 3, get!: 3, set: 4, set!: 4, remove: 3, remove!: 3,
+                     list: 2, list!: 2, dump: 2, dump!: 2
+    end
+  end
+end
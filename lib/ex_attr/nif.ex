@@ -9,16 +9,117 @@ defmodule ExAttr.Nif do
   def supported_platform,
     do: :erlang.nif_error(:nif_not_loaded)
 
-  def get_xattr(_path, _name),
+  def get_xattr(_path, _name, _follow),
     do: :erlang.nif_error(:nif_not_loaded)
 
-  def set_xattr(_path, _name, _value),
+  def set_xattr(_path, _name, _value, _follow, _mode),
     do: :erlang.nif_error(:nif_not_loaded)
 
-  def list_xattr(_path),
+  def list_xattr(_path, _follow, _prefix, _strip, _parse),
     do: :erlang.nif_error(:nif_not_loaded)
 
-  def remove_xattr(_path, _name),
+  def remove_xattr(_path, _name, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def dump_xattr(_path, _follow, _prefix, _strip, _parse),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def size_xattr(_path, _name, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def list_sizes_xattr(_path, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def open_handle(_path, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def handle_get_xattr(_handle, _name),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def handle_set_xattr(_handle, _name, _value, _mode),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def handle_list_xattr(_handle, _prefix, _strip, _parse),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def handle_remove_xattr(_handle, _name),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def handle_dump_xattr(_handle, _prefix, _strip, _parse),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def parse_name(_name),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def format_name(_name),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def dump_tree_xattr(_path, _follow, _max_depth, _cross_mounts, _prefix, _strip, _parse),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def start_tree_scan(
+        _path,
+        _tag,
+        _follow,
+        _max_depth,
+        _cross_mounts,
+        _prefix,
+        _strip,
+        _parse,
+        _threads,
+        _batch_size
+      ),
+      do: :erlang.nif_error(:nif_not_loaded)
+
+  def ack_tree_scan(_scan),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def open_tree_stream(_path, _follow, _max_depth, _cross_mounts, _prefix, _strip, _parse),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def next_tree_stream(_stream, _size),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def close_tree_stream(_stream),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def find_xattr(_path, _predicate, _follow, _max_depth, _cross_mounts),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def copy_xattr(_src, _dst, _follow, _namespaces, _prefix, _conflict, _remove_extra),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def diff_xattr(_left, _right, _follow, _prefix),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def patch_xattr(_path, _diff, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def export_xattr(_path, _follow, _max_depth, _cross_mounts, _prefix, _encoding),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def import_xattr(_dump, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def snapshot_xattr(_path, _format, _follow, _max_depth, _cross_mounts, _prefix),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def restore_xattr(_snapshot, _format, _follow),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def list_tar_xattr(_archive, _prefix),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def extract_tar_xattr(_archive, _dest, _prefix),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def create_tar_xattr(_archive, _path, _follow, _max_depth, _cross_mounts, _prefix),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def cp_xattr(_src, _dst, _namespaces, _prefix, _on_error),
+    do: :erlang.nif_error(:nif_not_loaded)
+
+  def write_xattr(_path, _contents, _attrs),
     do: :erlang.nif_error(:nif_not_loaded)
 
 end
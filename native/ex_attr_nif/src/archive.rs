@@ -0,0 +1,327 @@
+//! Attributes stored in tar archives.
+//!
+//! GNU tar and bsdtar record the attributes of every archived file as
+//! `SCHILY.xattr.<name>=<value>` records of a PAX extended header placed right before the
+//! file's own header. The records are read and written as raw bytes, so names and values
+//! don't have to be valid UTF-8.
+//!
+//! The tar crate splits PAX records on newlines, which values of binary attributes can
+//! hold, so archives are read twice: once in raw mode to parse the PAX headers by their
+//! record lengths, then normally to get at the entries those headers describe. Entries of
+//! both passes are matched up by the position of their header in the archive.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File, Permissions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Component, Path, PathBuf};
+
+use rustler::types::atom;
+use rustler::{Atom, Binary, Encoder, Env, NifResult, Term};
+use tar::{Archive, Builder, Entry, EntryType, Header};
+
+use crate::dump::Entries;
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::temp::TempFile;
+use crate::tree::{self, Options, Walker};
+use crate::{as_path, to_binary, to_map, validate};
+
+mod atoms {
+    rustler::atoms! {
+        failed,
+    }
+}
+
+const XATTR_KEY: &[u8] = b"SCHILY.xattr.";
+
+fn open(path: &Path) -> Result<Archive<BufReader<File>>, XattrError> {
+    let file = File::open(path).map_err(|e| XattrError::new(e, "open").with_path(path))?;
+    Ok(Archive::new(BufReader::new(file)))
+}
+
+// Errors from the tar crate carry their own context but no syscall
+fn tar_error(e: io::Error, path: &Path) -> XattrError {
+    XattrError::from(e).with_path(path)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "malformed pax extension")
+}
+
+// The `SCHILY.xattr` records of a PAX extended header matching `prefix`, every record
+// being `<length> <key>=<value>\n` with the length counting the whole record
+fn parse_xattrs(mut data: &[u8], prefix: Prefix) -> io::Result<Entries> {
+    let mut entries = Vec::new();
+    while !data.is_empty() {
+        let space = data.iter().position(|&byte| byte == b' ').ok_or_else(malformed)?;
+        let len = std::str::from_utf8(&data[..space]).ok().and_then(|len| len.parse().ok());
+        let len: usize =
+            len.filter(|&len| len > space + 1 && len <= data.len()).ok_or_else(malformed)?;
+        let record = data[space + 1..len].strip_suffix(b"\n").ok_or_else(malformed)?;
+        data = &data[len..];
+
+        let Some(record) = record.strip_prefix(XATTR_KEY) else { continue };
+        let equals = record.iter().position(|&byte| byte == b'=').ok_or_else(malformed)?;
+        if let Some(name) = prefix.apply(OsStr::from_bytes(&record[..equals])) {
+            entries.push((name.to_owned(), record[equals + 1..].to_vec()));
+        }
+    }
+    Ok(entries)
+}
+
+// The attributes of every entry the tar crate yields when not in raw mode, in order and
+// along with the position of the entry's header
+fn read_xattrs(path: &Path, prefix: Prefix) -> io::Result<Vec<(u64, Entries)>> {
+    let mut archive = Archive::new(BufReader::new(File::open(path)?));
+    let mut xattrs = Vec::new();
+    let mut pending = None;
+    for entry in archive.entries()?.raw(true) {
+        let mut entry = entry?;
+        let header = entry.header();
+        let entry_type = header.entry_type();
+        // Same checks the tar crate does before folding a header into the next entry
+        let recognized = header.as_gnu().is_some() || header.as_ustar().is_some();
+        if recognized && entry_type.is_pax_local_extensions() {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            pending = Some(parse_xattrs(&data, prefix)?);
+        } else if !(recognized && (entry_type.is_gnu_longname() || entry_type.is_gnu_longlink()))
+        {
+            xattrs.push((entry.raw_header_position(), pending.take().unwrap_or_default()));
+        }
+    }
+    Ok(xattrs)
+}
+
+// What the raw pass read for `entry`, which has to be the next thing it read. Both passes
+// only find the same headers as long as every entry is as long as its own header says,
+// which PAX `size` records and GNU sparse files break, so any mismatch fails the archive.
+fn paired<T, R: Read>(
+    read: &mut impl Iterator<Item = (u64, T)>,
+    entry: &Entry<R>,
+) -> io::Result<T> {
+    match read.next() {
+        Some((pos, value)) if pos == entry.raw_header_position() => Ok(value),
+        _ => Err(io::Error::new(io::ErrorKind::InvalidData, "pax headers out of step")),
+    }
+}
+
+// Where `unpack_in` puts an entry, `None` for the entries it refuses to unpack
+fn unpacked_path(dest: &Path, path: &Path) -> Option<PathBuf> {
+    let mut unpacked = dest.to_owned();
+    for component in path.components() {
+        match component {
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => (),
+            Component::ParentDir => return None,
+            Component::Normal(part) => unpacked.push(part),
+        }
+    }
+    Some(unpacked)
+}
+
+// A PAX extended header holding one `SCHILY.xattr` record per attribute. Every record
+// starts with its own length in decimal, which counts the digits of the length itself.
+fn append_xattrs<W: Write>(builder: &mut Builder<W>, entries: &Entries) -> io::Result<()> {
+    let mut data = Vec::new();
+    for (name, value) in entries {
+        let rest = b" =\n".len() + XATTR_KEY.len() + name.len() + value.len();
+        let mut len = rest + 1;
+        while len != rest + len.to_string().len() {
+            len = rest + len.to_string().len();
+        }
+        data.extend_from_slice(format!("{len} ").as_bytes());
+        data.extend_from_slice(XATTR_KEY);
+        data.extend_from_slice(name.as_bytes());
+        data.push(b'=');
+        data.extend_from_slice(value);
+        data.push(b'\n');
+    }
+
+    let mut header = Header::new_ustar();
+    header.set_entry_type(EntryType::XHeader);
+    header.set_size(data.len() as u64);
+    header.set_cksum();
+    builder.append(&header, data.as_slice())
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn list_tar_xattr<'a>(
+    env: Env<'a>,
+    archive: Binary,
+    prefix: Option<Binary>,
+) -> NifResult<Vec<(Binary<'a>, Term<'a>)>> {
+    let path = as_path(&archive);
+    let prefix = Prefix::new(prefix.as_ref(), false);
+    let mut archive = open(path)?;
+    let mut xattrs = read_xattrs(path, prefix).map_err(|e| tar_error(e, path))?.into_iter();
+
+    let mut listed = Vec::new();
+    for entry in archive.entries().map_err(|e| tar_error(e, path))? {
+        let entry = entry.map_err(|e| tar_error(e, path))?;
+        let entries = paired(&mut xattrs, &entry).map_err(|e| tar_error(e, path))?;
+        if !entries.is_empty() {
+            let name = to_binary(env, &entry.path_bytes())?;
+            listed.push((name, to_map(env, entries, false)?));
+        }
+    }
+    Ok(listed)
+}
+
+// Attributes that failed to be set, along with the file they were meant for
+type Failures = Vec<(PathBuf, Vec<(OsString, XattrError)>)>;
+
+// Entries are unpacked with `unpack_in`, except for directories which are held back until
+// the end and then unpacked deepest first, the same as `tar::Archive::unpack` does so that
+// their permissions don't keep anything from being unpacked into them. Entries with a `..`
+// component are skipped.
+//
+// Every record is checked before anything is unpacked. Invalid ones and the attributes
+// that can't be set (ex: `trusted.*` without the privilege for it) are reported rather
+// than stopping the extraction halfway.
+fn extract(path: &Path, dest: &Path, prefix: Prefix) -> Result<Failures, XattrError> {
+    let mut archive = open(path)?;
+    let xattrs = read_xattrs(path, prefix).map_err(|e| tar_error(e, path))?;
+    let mut checked = Vec::with_capacity(xattrs.len());
+    for (pos, entries) in xattrs {
+        let (mut valid, mut invalid) = (Vec::new(), Vec::new());
+        for (name, value) in entries {
+            match validate::name(&name).and_then(|_| validate::value(&name, &value)) {
+                Ok(_) => valid.push((name, value)),
+                Err(e) => invalid.push((name, e)),
+            }
+        }
+        checked.push((pos, (valid, invalid)));
+    }
+    let mut checked = checked.into_iter();
+
+    let mut failures = Vec::new();
+    let mut unpack = |entry: &mut Entry<_>, unpacked: PathBuf, valid, mut failed: Vec<_>| {
+        if entry.unpack_in(dest).map_err(|e| tar_error(e, &unpacked))? {
+            set_xattrs(&unpacked, valid, &mut failed)?;
+            if !failed.is_empty() {
+                failures.push((unpacked, failed));
+            }
+        }
+        Ok::<_, XattrError>(())
+    };
+
+    let mut directories = Vec::new();
+    for entry in archive.entries().map_err(|e| tar_error(e, path))? {
+        let mut entry = entry.map_err(|e| tar_error(e, path))?;
+        let (valid, failed) = paired(&mut checked, &entry).map_err(|e| tar_error(e, path))?;
+        let entry_path = entry.path().map_err(|e| tar_error(e, path))?;
+        let Some(unpacked) = unpacked_path(dest, &entry_path) else { continue };
+        if entry.header().entry_type() == EntryType::Directory {
+            directories.push((entry, unpacked, valid, failed));
+        } else {
+            unpack(&mut entry, unpacked, valid, failed)?;
+        }
+    }
+    directories.sort_by(|(a, ..), (b, ..)| b.path_bytes().cmp(&a.path_bytes()));
+    for (mut entry, unpacked, valid, failed) in directories {
+        unpack(&mut entry, unpacked, valid, failed)?;
+    }
+    Ok(failures)
+}
+
+// `unpack_in` has already applied the archived mode by now, and attributes can't be set
+// on files their owner can't write to, so those are made writable for as long as it takes
+fn set_xattrs(
+    path: &Path,
+    entries: Entries,
+    failed: &mut Vec<(OsString, XattrError)>,
+) -> Result<(), XattrError> {
+    if entries.is_empty() {
+        return Ok(());
+    }
+    let metadata =
+        fs::symlink_metadata(path).map_err(|e| XattrError::new(e, "lstat").with_path(path))?;
+    let mode = metadata.permissions().mode();
+    let made_writable = (metadata.is_file() || metadata.is_dir())
+        && mode & 0o200 == 0
+        && fs::set_permissions(path, Permissions::from_mode(mode | 0o200)).is_ok();
+
+    for (name, value) in entries {
+        if let Err(e) = xattr::set(path, &name, &value) {
+            let e = XattrError::new(e, "lsetxattr").with_name(&name);
+            failed.push((name, e));
+        }
+    }
+    if made_writable {
+        fs::set_permissions(path, Permissions::from_mode(mode))
+            .map_err(|e| XattrError::new(e, "chmod").with_path(path))?;
+    }
+    Ok(())
+}
+
+// Entries are named relative to the parent of `root`, like `tar -cf archive root` does.
+// The archive is built next to `path` and only replaces it once complete, leaving out both
+// of them when they are inside `root`.
+fn create(path: &Path, root: &Path, options: Options, prefix: Prefix) -> Result<(), XattrError> {
+    let follow = options.follow;
+    let base = root.parent().unwrap_or(Path::new(""));
+    let temp = TempFile::new(path, 0o666)?;
+    let built = temp.file.metadata().map_err(|e| XattrError::new(e, "fstat").with_path(path))?;
+    let mut archives = vec![(built.dev(), built.ino())];
+    if let Ok(replaced) = fs::metadata(path) {
+        archives.push((replaced.dev(), replaced.ino()));
+    }
+
+    let mut builder = Builder::new(BufWriter::new(&temp.file));
+    builder.follow_symlinks(follow);
+    for entry_path in Walker::new(root.to_owned(), options) {
+        let entry_path = entry_path?;
+        let metadata = tree::metadata(&entry_path, follow).map_err(|e| e.with_path(&entry_path))?;
+        if archives.contains(&(metadata.dev(), metadata.ino())) {
+            continue;
+        }
+        if let Some(entries) = tree::dump_entry(&entry_path, follow, prefix)? {
+            append_xattrs(&mut builder, &entries).map_err(|e| tar_error(e, path))?;
+        }
+        let name = entry_path.strip_prefix(base).unwrap_or(&entry_path);
+        builder.append_path_with_name(&entry_path, name).map_err(|e| tar_error(e, &entry_path))?;
+    }
+    builder
+        .into_inner()
+        .and_then(|mut writer| writer.flush())
+        .map_err(|e| tar_error(e, path))?;
+    temp.persist(path)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn extract_tar_xattr<'a>(
+    env: Env<'a>,
+    archive: Binary,
+    dest: Binary,
+    prefix: Option<Binary>,
+) -> NifResult<Term<'a>> {
+    let prefix = Prefix::new(prefix.as_ref(), false);
+    let failures = extract(as_path(&archive), as_path(&dest), prefix)?;
+
+    let mut failed = Vec::with_capacity(failures.len());
+    for (path, attrs) in failures {
+        let attrs = attrs
+            .iter()
+            .map(|(name, e)| Ok((to_binary(env, name.as_bytes())?, e.encode_reason(env))))
+            .collect::<NifResult<Vec<_>>>()?;
+        failed.push((to_binary(env, path.as_os_str().as_bytes())?, attrs));
+    }
+    Term::map_from_arrays(env, &[atoms::failed().encode(env)], &[failed.encode(env)])
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn create_tar_xattr(
+    archive: Binary,
+    root: Binary,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+) -> NifResult<Atom> {
+    let options = Options { max_depth, follow, cross_mounts };
+    let prefix = Prefix::new(prefix.as_ref(), false);
+    create(as_path(&archive), as_path(&root), options, prefix)?;
+    Ok(atom::ok())
+}
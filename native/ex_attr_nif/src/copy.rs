@@ -0,0 +1,175 @@
+//! Copying attributes from one file onto another.
+//!
+//! The source is dumped once, then every selected attribute is set on the destination on
+//! its own. A failing attribute (ex: `security.*` without the privilege for it) is
+//! reported along with the others instead of stopping the copy, only failing to read
+//! either file at all aborts it.
+
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use rustix::io::Errno;
+use rustler::{Binary, Encoder, Env, NifResult, Term};
+
+use crate::error::XattrError;
+use crate::name::{self, Namespace};
+use crate::prefix::Prefix;
+use crate::{as_path, dump, sys, to_binary, SetMode};
+
+mod atoms {
+    rustler::atoms! {
+        copied,
+        skipped,
+        removed,
+        failed,
+    }
+}
+
+// What to do with attributes the destination already has
+#[derive(Clone, Copy, PartialEq, rustler::NifUnitEnum)]
+pub enum Conflict {
+    Overwrite,
+    Skip,
+}
+
+// Which attributes an operation applies to, every one of them by default
+pub struct Selection<'a> {
+    namespaces: Option<Vec<Namespace>>,
+    prefix: Prefix<'a>,
+}
+
+impl<'a> Selection<'a> {
+    pub fn new(namespaces: Option<Vec<Namespace>>, prefix: Option<&'a Binary>) -> Self {
+        Selection { namespaces, prefix: Prefix::new(prefix, false) }
+    }
+
+    pub fn matches(&self, name: &OsStr) -> bool {
+        let in_namespace = |namespaces: &Vec<Namespace>| {
+            let (namespace, _) = name::split(name.as_bytes());
+            namespace.is_some_and(|namespace| namespaces.contains(&namespace))
+        };
+        self.prefix.apply(name).is_some() && self.namespaces.as_ref().is_none_or(in_namespace)
+    }
+}
+
+#[derive(Default)]
+pub struct Report {
+    pub copied: Vec<OsString>,
+    pub skipped: Vec<OsString>,
+    pub removed: Vec<OsString>,
+    pub failed: Vec<(OsString, XattrError)>,
+}
+
+impl Report {
+    pub fn encode<'a>(self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let names = |names: Vec<OsString>| -> NifResult<Vec<Binary<'a>>> {
+            names.iter().map(|name| to_binary(env, name.as_bytes())).collect()
+        };
+        let failed = self
+            .failed
+            .iter()
+            .map(|(name, e)| Ok((to_binary(env, name.as_bytes())?, e.encode_reason(env))))
+            .collect::<NifResult<Vec<_>>>()?;
+
+        Term::map_from_arrays(
+            env,
+            &[
+                atoms::copied().encode(env),
+                atoms::skipped().encode(env),
+                atoms::removed().encode(env),
+                atoms::failed().encode(env),
+            ],
+            &[
+                names(self.copied)?.encode(env),
+                names(self.skipped)?.encode(env),
+                names(self.removed)?.encode(env),
+                failed.encode(env),
+            ],
+        )
+    }
+}
+
+fn copy(
+    src: &Path,
+    dst: &Path,
+    follow: bool,
+    selection: &Selection,
+    conflict: Conflict,
+    remove_extra: bool,
+) -> Result<Report, XattrError> {
+    let (list_syscall, set_syscall, remove_syscall) = if follow {
+        ("listxattr", "setxattr", "removexattr")
+    } else {
+        ("llistxattr", "lsetxattr", "lremovexattr")
+    };
+
+    let mut source = dump::dump(src, follow, Prefix::new(None, false))
+        .map_err(|e| e.with_path(src))?;
+    source.retain(|(name, _)| selection.matches(name));
+
+    let existing = if follow { xattr::list_deref(dst) } else { xattr::list(dst) };
+    let existing = existing
+        .map_err(|e| XattrError::new(e, list_syscall).with_path(dst))?
+        .filter(|name| selection.matches(name))
+        .collect::<HashSet<_>>();
+
+    let mut report = Report::default();
+    let mut in_source = HashSet::new();
+    for (name, value) in source {
+        in_source.insert(name.clone());
+        if conflict == Conflict::Skip && existing.contains(&name) {
+            report.skipped.push(name);
+            continue;
+        }
+        let result = match conflict {
+            Conflict::Overwrite if follow => xattr::set_deref(dst, &name, &value),
+            Conflict::Overwrite => xattr::set(dst, &name, &value),
+            // Still atomic in case the attribute showed up since listing them
+            Conflict::Skip => sys::set(dst, &name, &value, follow, SetMode::Create),
+        };
+        match result {
+            Ok(_) => report.copied.push(name),
+            Err(e) if e.raw_os_error() == Some(Errno::EXIST.raw_os_error()) => {
+                report.skipped.push(name)
+            }
+            Err(e) => {
+                let e = XattrError::new(e, set_syscall).with_name(&name);
+                report.failed.push((name, e));
+            }
+        }
+    }
+
+    if remove_extra {
+        for name in existing.into_iter().filter(|name| !in_source.contains(name)) {
+            let result =
+                if follow { xattr::remove_deref(dst, &name) } else { xattr::remove(dst, &name) };
+            match result {
+                Ok(_) => report.removed.push(name),
+                Err(e) => {
+                    let e = XattrError::new(e, remove_syscall).with_name(&name);
+                    report.failed.push((name, e));
+                }
+            }
+        }
+    }
+    Ok(report)
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rustler::nif(schedule = "DirtyIo")]
+fn copy_xattr<'a>(
+    env: Env<'a>,
+    src: Binary,
+    dst: Binary,
+    follow: bool,
+    namespaces: Option<Vec<Namespace>>,
+    prefix: Option<Binary>,
+    conflict: Conflict,
+    remove_extra: bool,
+) -> NifResult<Term<'a>> {
+    let selection = Selection::new(namespaces, prefix.as_ref());
+    let (src, dst) = (as_path(&src), as_path(&dst));
+    copy(src, dst, follow, &selection, conflict, remove_extra)?.encode(env)
+}
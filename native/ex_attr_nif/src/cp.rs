@@ -0,0 +1,88 @@
+//! Copying a file along with its attributes.
+//!
+//! The data and the selected attributes of the source are copied onto a temporary file
+//! next to the destination, which is then renamed over it. The destination is never seen
+//! half written or without its attributes, and is left untouched if anything fails.
+
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use rustler::{Binary, Env, NifResult, Term};
+
+use crate::copy::{Report, Selection};
+use crate::error::XattrError;
+use crate::name::Namespace;
+use crate::prefix::Prefix;
+use crate::temp::TempFile;
+use crate::{as_path, dump, sys, SetMode};
+
+// What to do with attributes that can't be set on the copy
+#[derive(Clone, Copy, PartialEq, rustler::NifUnitEnum)]
+pub enum OnError {
+    Fail,
+    Skip,
+}
+
+// Shares the source's extents through a reflink where the filesystem supports it (btrfs,
+// XFS, ...). Otherwise `io::copy` goes through copy_file_range(2) or sendfile(2) on Linux
+// and only falls back to reading and writing when neither works.
+fn copy_data(src: &File, dst: &File) -> io::Result<()> {
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    if rustix::fs::ioctl_ficlone(dst, src).is_ok() {
+        return Ok(());
+    }
+    io::copy(&mut &*src, &mut &*dst)?;
+    Ok(())
+}
+
+fn cp(
+    src: &Path,
+    dst: &Path,
+    selection: &Selection,
+    on_error: OnError,
+) -> Result<Report, XattrError> {
+    let source = File::open(src).map_err(|e| XattrError::new(e, "open").with_path(src))?;
+    let metadata = source.metadata().map_err(|e| XattrError::new(e, "fstat").with_path(src))?;
+    // Read through the descriptor the data is copied from, so both come from the same file
+    let mut attrs =
+        dump::dump_file(&source, Prefix::new(None, false)).map_err(|e| e.with_path(src))?;
+    attrs.retain(|(name, _)| selection.matches(name));
+
+    let temp = TempFile::new(dst, 0o600)?;
+    copy_data(&source, &temp.file).map_err(|e| XattrError::from(e).with_path(dst))?;
+
+    let mut report = Report::default();
+    for (name, value) in attrs {
+        match sys::fset(&temp.file, &name, &value, SetMode::Upsert) {
+            Ok(_) => report.copied.push(name),
+            Err(e) => {
+                let e = XattrError::new(e, "fsetxattr").with_name(&name).with_path(dst);
+                if on_error == OnError::Fail {
+                    return Err(e);
+                }
+                report.failed.push((name, e));
+            }
+        }
+    }
+
+    // Only now, a read-only mode would keep the attributes above from being set
+    temp.file
+        .set_permissions(metadata.permissions())
+        .map_err(|e| XattrError::new(e, "fchmod").with_path(dst))?;
+    temp.persist(dst)?;
+    Ok(report)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn cp_xattr<'a>(
+    env: Env<'a>,
+    src: Binary,
+    dst: Binary,
+    namespaces: Option<Vec<Namespace>>,
+    prefix: Option<Binary>,
+    on_error: OnError,
+) -> NifResult<Term<'a>> {
+    let selection = Selection::new(namespaces, prefix.as_ref());
+    cp(as_path(&src), as_path(&dst), &selection, on_error)?.encode(env)
+}
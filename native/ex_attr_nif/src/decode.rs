@@ -0,0 +1,32 @@
+//! Attribute names and values decoded from terms passed in by the caller.
+//!
+//! Invalid names are reported like everywhere else, anything else malformed raises badarg.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+
+use rustler::types::map::MapIterator;
+use rustler::{Binary, Error, NifResult, Term};
+
+use crate::{as_os_str, validate};
+
+pub type Attrs = BTreeMap<OsString, Vec<u8>>;
+
+pub fn name(term: Term) -> NifResult<OsString> {
+    let name: Binary = term.decode()?;
+    let name = as_os_str(&name);
+    validate::name(name)?;
+    Ok(name.to_owned())
+}
+
+pub fn bytes(term: Term) -> NifResult<Vec<u8>> {
+    Ok(term.decode::<Binary>()?.as_slice().to_vec())
+}
+
+// A map of names to values, such as one returned by `dump/2`
+pub fn attrs(term: Term) -> NifResult<Attrs> {
+    MapIterator::new(term)
+        .ok_or(Error::BadArg)?
+        .map(|(name, value)| Ok((self::name(name)?, bytes(value)?)))
+        .collect()
+}
@@ -0,0 +1,152 @@
+//! Differences between the attributes of two files, and applying them back.
+//!
+//! Either side of a diff is a path, dumped natively, or a map of names to values such as
+//! one returned by `dump/2`. The diff is returned as
+//! `%{added: %{name => value}, removed: %{name => value}, changed: %{name => {old, new}}}`,
+//! which `patch_xattr` takes as is to turn the left side into the right one.
+
+use std::collections::BTreeMap;
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStrExt;
+
+use rustler::types::atom;
+use rustler::types::map::MapIterator;
+use rustler::{Atom, Binary, Encoder, Env, Error, NifResult, Term};
+
+use crate::decode::{self, Attrs};
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::{as_path, dump, to_binary, validate};
+
+mod atoms {
+    rustler::atoms! {
+        added,
+        removed,
+        changed,
+    }
+}
+
+fn load(side: Term, follow: bool, prefix: Prefix) -> NifResult<Attrs> {
+    if side.is_map() {
+        let mut attrs = decode::attrs(side)?;
+        attrs.retain(|name, _| prefix.apply(name).is_some());
+        return Ok(attrs);
+    }
+    let path: Binary = side.decode()?;
+    let path = as_path(&path);
+    let entries = dump::dump(path, follow, prefix).map_err(|e| e.with_path(path))?;
+    Ok(entries.into_iter().collect())
+}
+
+#[derive(Default)]
+struct Diff {
+    added: Attrs,
+    removed: Attrs,
+    changed: BTreeMap<OsString, (Vec<u8>, Vec<u8>)>,
+}
+
+impl Diff {
+    fn new(left: Attrs, mut right: Attrs) -> Self {
+        let mut diff = Diff::default();
+        for (name, old) in left {
+            match right.remove(&name) {
+                None => {
+                    diff.removed.insert(name, old);
+                }
+                Some(new) if new != old => {
+                    diff.changed.insert(name, (old, new));
+                }
+                Some(_) => (),
+            }
+        }
+        diff.added = right;
+        diff
+    }
+
+    fn encode<'a>(self, env: Env<'a>) -> NifResult<Term<'a>> {
+        let binary = |bytes: &[u8]| to_binary(env, bytes);
+        let attrs = |attrs: Attrs| -> NifResult<Term<'a>> {
+            let mut map = Term::map_new(env);
+            for (name, value) in attrs {
+                map = map.map_put(binary(name.as_bytes())?, binary(&value)?)?;
+            }
+            Ok(map)
+        };
+
+        let mut changed = Term::map_new(env);
+        for (name, (old, new)) in self.changed {
+            changed = changed.map_put(binary(name.as_bytes())?, (binary(&old)?, binary(&new)?))?;
+        }
+
+        Term::map_from_arrays(
+            env,
+            &[
+                atoms::added().encode(env),
+                atoms::removed().encode(env),
+                atoms::changed().encode(env),
+            ],
+            &[attrs(self.added)?, attrs(self.removed)?, changed],
+        )
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn diff_xattr<'a>(
+    env: Env<'a>,
+    left: Term<'a>,
+    right: Term<'a>,
+    follow: bool,
+    prefix: Option<Binary>,
+) -> NifResult<Term<'a>> {
+    let prefix = Prefix::new(prefix.as_ref(), false);
+    let (left, right) = (load(left, follow, prefix)?, load(right, follow, prefix)?);
+    Diff::new(left, right).encode(env)
+}
+
+// Keys missing from the diff are treated as empty so hand written diffs can leave them out
+fn decode_diff(term: Term) -> NifResult<Diff> {
+    let mut diff = Diff::default();
+    if let Ok(added) = term.map_get(atoms::added()) {
+        diff.added = decode::attrs(added)?;
+    }
+    if let Ok(removed) = term.map_get(atoms::removed()) {
+        diff.removed = decode::attrs(removed)?;
+    }
+    if let Ok(changed) = term.map_get(atoms::changed()) {
+        for (name, values) in MapIterator::new(changed).ok_or(Error::BadArg)? {
+            let (old, new): (Term, Term) = values.decode()?;
+            diff.changed.insert(decode::name(name)?, (decode::bytes(old)?, decode::bytes(new)?));
+        }
+    }
+    Ok(diff)
+}
+
+// Removals go first, then every added and changed attribute is set to its new value. The
+// first failure stops the patch, leaving whatever was applied before it in place.
+#[rustler::nif(schedule = "DirtyIo")]
+fn patch_xattr(path: Binary, diff: Term, follow: bool) -> NifResult<Atom> {
+    let path = as_path(&path);
+    let diff = decode_diff(diff)?;
+    let (set_syscall, remove_syscall) =
+        if follow { ("setxattr", "removexattr") } else { ("lsetxattr", "lremovexattr") };
+    let changed = diff.changed.into_iter().map(|(name, (_, new))| (name, new));
+    let sets = diff.added.into_iter().chain(changed).collect::<Vec<_>>();
+    for (name, value) in &sets {
+        validate::value(name, value)?;
+    }
+
+    for name in diff.removed.keys() {
+        let result =
+            if follow { xattr::remove_deref(path, name) } else { xattr::remove(path, name) };
+        result.map_err(|e| XattrError::new(e, remove_syscall).with_name(name))?;
+    }
+    for (name, value) in &sets {
+        let result = if follow {
+            xattr::set_deref(path, name, value)
+        } else {
+            xattr::set(path, name, value)
+        };
+        result.map_err(|e| XattrError::new(e, set_syscall).with_name(name))?;
+    }
+    Ok(atom::ok())
+}
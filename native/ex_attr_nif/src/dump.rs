@@ -0,0 +1,72 @@
+//! Reads every attribute of a file in a single pass.
+//!
+//! Whenever possible the file is opened once and all attributes are listed and read
+//! through that one descriptor, so the file can't be swapped out from under us halfway
+//! through. Attributes that vanish between being listed and being read are skipped.
+
+use std::ffi::OsString;
+use std::fs::{self, File};
+use std::path::Path;
+
+use xattr::FileExt;
+
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::sys;
+
+pub type Entries = Vec<(OsString, Vec<u8>)>;
+
+// Only names matching `prefix` are read, and they are reported the way `prefix` says
+pub fn dump(path: &Path, follow: bool, prefix: Prefix) -> Result<Entries, XattrError> {
+    match open(path, follow) {
+        Some(file) => dump_file(&file, prefix),
+        None => {
+            let (list_syscall, get_syscall) = if follow {
+                ("listxattr", "getxattr")
+            } else {
+                ("llistxattr", "lgetxattr")
+            };
+            let list = if follow { xattr::list_deref(path) } else { xattr::list(path) };
+            let mut entries = Vec::new();
+            for name in list.map_err(|e| XattrError::new(e, list_syscall))? {
+                let Some(key) = prefix.apply(&name) else { continue };
+                let value = if follow {
+                    xattr::get_deref(path, &name)
+                } else {
+                    xattr::get(path, &name)
+                };
+                let value = value.map_err(|e| XattrError::new(e, get_syscall).with_name(&name))?;
+                if let Some(value) = value {
+                    entries.push((key.to_owned(), value));
+                }
+            }
+            Ok(entries)
+        }
+    }
+}
+
+pub fn dump_file(file: &File, prefix: Prefix) -> Result<Entries, XattrError> {
+    let mut entries = Vec::new();
+    let list = file.list_xattr().map_err(|e| XattrError::new(e, "flistxattr"))?;
+    for name in list {
+        let Some(key) = prefix.apply(&name) else { continue };
+        let value = file.get_xattr(&name)
+            .map_err(|e| XattrError::new(e, "fgetxattr").with_name(&name))?;
+        if let Some(value) = value {
+            entries.push((key.to_owned(), value));
+        }
+    }
+    Ok(entries)
+}
+
+// Only regular files and directories are opened, opening devices can have side effects
+// and symlinks can't be opened without following them. Anything that can't be opened
+// (permissions, races, etc) falls back to path based calls which report the real error.
+fn open(path: &Path, follow: bool) -> Option<File> {
+    let metadata = if follow { fs::metadata(path) } else { fs::symlink_metadata(path) };
+    match metadata {
+        Ok(metadata) if metadata.is_file() || metadata.is_dir() => (),
+        _ => return None,
+    }
+    sys::open(path, follow).ok()
+}
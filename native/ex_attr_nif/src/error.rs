@@ -0,0 +1,340 @@
+//! Errors returned from the NIFs.
+//!
+//! Every failure is returned as `{:error, %{reason: _, errno: _, syscall: _, name: _}}`
+//! where `reason` is the posix atom (or a string when the error didn't come from the OS),
+//! `errno` the raw error number, `syscall` the call that failed and `name` the attribute
+//! it failed on. `syscall`, `errno` and `name` are `nil` when they don't apply.
+//!
+//! Errors from operations spanning many files (ex: tree walks) also carry the `path` of
+//! the file they failed on, the other NIFs leave it to the caller.
+//!
+//! Input rejected before making any syscall has a `{:invalid_name, kind}` or
+//! `{:invalid_value, kind}` reason instead. Malformed dumps being imported have an
+//! `{:invalid_dump, line}` reason and malformed snapshots an `{:invalid_snapshot, kind}` one.
+
+use std::ffi::{OsStr, OsString};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use rustler::{Atom, Encoder, Env, Term};
+use rustix::io::Errno;
+
+use crate::to_binary;
+
+mod atoms {
+    rustler::atoms! {
+        reason,
+        errno,
+        syscall,
+        name,
+        path,
+        invalid_name,
+        invalid_value,
+        invalid_dump,
+        invalid_snapshot,
+    }
+}
+
+enum Reason {
+    Io(io::Error),
+    InvalidName(&'static str),
+    InvalidValue(&'static str),
+    InvalidDump(usize),
+    InvalidSnapshot(&'static str),
+}
+
+pub struct XattrError {
+    reason: Reason,
+    syscall: Option<&'static str>,
+    name: Option<OsString>,
+    path: Option<PathBuf>,
+}
+
+impl XattrError {
+    pub fn new(err: io::Error, syscall: &'static str) -> Self {
+        XattrError { syscall: Some(syscall), ..Reason::Io(err).into() }
+    }
+
+    pub fn invalid_name(kind: &'static str) -> Self {
+        Reason::InvalidName(kind).into()
+    }
+
+    pub fn invalid_value(kind: &'static str) -> Self {
+        Reason::InvalidValue(kind).into()
+    }
+
+    // `line` is 1-based
+    pub fn invalid_dump(line: usize) -> Self {
+        Reason::InvalidDump(line).into()
+    }
+
+    pub fn invalid_snapshot(kind: &'static str) -> Self {
+        Reason::InvalidSnapshot(kind).into()
+    }
+
+    pub fn with_name(mut self, name: &OsStr) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_owned());
+        self
+    }
+
+    // Whether the file being operated on is gone, which walks treat as a race to skip
+    pub fn is_vanished(&self) -> bool {
+        matches!(&self.reason, Reason::Io(err) if err.kind() == io::ErrorKind::NotFound)
+    }
+
+    // Only the reason, for reporting many failures at once without all the details
+    pub fn encode_reason<'a>(&self, env: Env<'a>) -> Term<'a> {
+        match &self.reason {
+            Reason::Io(err) => match io_error_to_atom(err) {
+                Ok(atom_str) => Atom::from_str(env, atom_str).unwrap().encode(env),
+                Err(msg) => msg.encode(env),
+            },
+            Reason::InvalidName(kind) => {
+                let kind = Atom::from_str(env, kind).unwrap();
+                (atoms::invalid_name(), kind).encode(env)
+            }
+            Reason::InvalidValue(kind) => {
+                let kind = Atom::from_str(env, kind).unwrap();
+                (atoms::invalid_value(), kind).encode(env)
+            }
+            Reason::InvalidDump(line) => (atoms::invalid_dump(), *line).encode(env),
+            Reason::InvalidSnapshot(kind) => {
+                let kind = Atom::from_str(env, kind).unwrap();
+                (atoms::invalid_snapshot(), kind).encode(env)
+            }
+        }
+    }
+}
+
+impl From<io::Error> for XattrError {
+    fn from(err: io::Error) -> Self {
+        Reason::Io(err).into()
+    }
+}
+
+impl From<Reason> for XattrError {
+    fn from(reason: Reason) -> Self {
+        XattrError { reason, syscall: None, name: None, path: None }
+    }
+}
+
+impl From<XattrError> for rustler::Error {
+    fn from(err: XattrError) -> Self {
+        rustler::Error::Term(Box::new(err))
+    }
+}
+
+impl Encoder for XattrError {
+    fn encode<'a>(&self, env: Env<'a>) -> Term<'a> {
+        let reason = self.encode_reason(env);
+        let errno = match &self.reason {
+            Reason::Io(err) => err.raw_os_error(),
+            _ => None,
+        };
+        let syscall = self.syscall.map(|syscall| Atom::from_str(env, syscall).unwrap());
+        let name = self.name.as_ref().map(|name| to_binary(env, name.as_bytes()).unwrap());
+
+        let map = Term::map_from_arrays(
+            env,
+            &[
+                atoms::reason().encode(env),
+                atoms::errno().encode(env),
+                atoms::syscall().encode(env),
+                atoms::name().encode(env),
+            ],
+            &[reason, errno.encode(env), syscall.encode(env), name.encode(env)],
+        )
+        .unwrap();
+        match &self.path {
+            Some(path) => {
+                let path = to_binary(env, path.as_os_str().as_bytes()).unwrap();
+                map.map_put(atoms::path(), path).unwrap()
+            }
+            None => map,
+        }
+    }
+}
+
+// Every errno rustix knows about paired with the lowercase posix atom erlang uses for it,
+// see `:erl_posix_msg`. Errnos that only exist on some platforms live in
+// `PLATFORM_ERRNO_ATOMS`. Aliases sharing a value with an earlier entry (`OPNOTSUPP` and
+// `WOULDBLOCK` on Linux) never match, so the first name listed wins.
+const ERRNO_ATOMS: &[(Errno, &str)] = &[
+    (Errno::ACCESS,         "eacces"),
+    (Errno::ADDRINUSE,      "eaddrinuse"),
+    (Errno::ADDRNOTAVAIL,   "eaddrnotavail"),
+    (Errno::AFNOSUPPORT,    "eafnosupport"),
+    (Errno::AGAIN,          "eagain"),
+    (Errno::ALREADY,        "ealready"),
+    (Errno::BADF,           "ebadf"),
+    (Errno::BADMSG,         "ebadmsg"),
+    (Errno::BUSY,           "ebusy"),
+    (Errno::CANCELED,       "ecanceled"),
+    (Errno::CHILD,          "echild"),
+    (Errno::CONNABORTED,    "econnaborted"),
+    (Errno::CONNREFUSED,    "econnrefused"),
+    (Errno::CONNRESET,      "econnreset"),
+    (Errno::DEADLK,         "edeadlk"),
+    (Errno::DESTADDRREQ,    "edestaddrreq"),
+    (Errno::DOM,            "edom"),
+    (Errno::DQUOT,          "edquot"),
+    (Errno::EXIST,          "eexist"),
+    (Errno::FAULT,          "efault"),
+    (Errno::FBIG,           "efbig"),
+    (Errno::HOSTDOWN,       "ehostdown"),
+    (Errno::HOSTUNREACH,    "ehostunreach"),
+    (Errno::IDRM,           "eidrm"),
+    (Errno::ILSEQ,          "eilseq"),
+    (Errno::INPROGRESS,     "einprogress"),
+    (Errno::INTR,           "eintr"),
+    (Errno::INVAL,          "einval"),
+    (Errno::IO,             "eio"),
+    (Errno::ISCONN,         "eisconn"),
+    (Errno::ISDIR,          "eisdir"),
+    (Errno::LOOP,           "eloop"),
+    (Errno::MFILE,          "emfile"),
+    (Errno::MLINK,          "emlink"),
+    (Errno::MSGSIZE,        "emsgsize"),
+    (Errno::NAMETOOLONG,    "enametoolong"),
+    (Errno::NETDOWN,        "enetdown"),
+    (Errno::NETRESET,       "enetreset"),
+    (Errno::NETUNREACH,     "enetunreach"),
+    (Errno::NFILE,          "enfile"),
+    (Errno::NOBUFS,         "enobufs"),
+    (Errno::NODEV,          "enodev"),
+    (Errno::NOENT,          "enoent"),
+    (Errno::NOEXEC,         "enoexec"),
+    (Errno::NOLCK,          "enolck"),
+    (Errno::NOMEM,          "enomem"),
+    (Errno::NOMSG,          "enomsg"),
+    (Errno::NOPROTOOPT,     "enoprotoopt"),
+    (Errno::NOSPC,          "enospc"),
+    (Errno::NOSYS,          "enosys"),
+    (Errno::NOTBLK,         "enotblk"),
+    (Errno::NOTCONN,        "enotconn"),
+    (Errno::NOTDIR,         "enotdir"),
+    (Errno::NOTEMPTY,       "enotempty"),
+    (Errno::NOTSOCK,        "enotsock"),
+    (Errno::NOTSUP,         "enotsup"),
+    (Errno::NOTTY,          "enotty"),
+    (Errno::NXIO,           "enxio"),
+    (Errno::OPNOTSUPP,      "eopnotsupp"),
+    (Errno::OVERFLOW,       "eoverflow"),
+    (Errno::PERM,           "eperm"),
+    (Errno::PFNOSUPPORT,    "epfnosupport"),
+    (Errno::PIPE,           "epipe"),
+    (Errno::PROTO,          "eproto"),
+    (Errno::PROTONOSUPPORT, "eprotonosupport"),
+    (Errno::PROTOTYPE,      "eprototype"),
+    (Errno::RANGE,          "erange"),
+    (Errno::REMOTE,         "eremote"),
+    (Errno::ROFS,           "erofs"),
+    (Errno::SHUTDOWN,       "eshutdown"),
+    (Errno::SOCKTNOSUPPORT, "esocktnosupport"),
+    (Errno::SPIPE,          "espipe"),
+    (Errno::SRCH,           "esrch"),
+    (Errno::STALE,          "estale"),
+    (Errno::TIMEDOUT,       "etimedout"),
+    (Errno::TOOBIG,         "e2big"),
+    (Errno::TOOMANYREFS,    "etoomanyrefs"),
+    (Errno::TXTBSY,         "etxtbsy"),
+    (Errno::USERS,          "eusers"),
+    (Errno::WOULDBLOCK,     "ewouldblock"),
+    (Errno::XDEV,           "exdev"),
+];
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const PLATFORM_ERRNO_ATOMS: &[(Errno, &str)] = &[
+    (Errno::ADV,            "eadv"),
+    (Errno::BADE,           "ebade"),
+    (Errno::BADFD,          "ebadfd"),
+    (Errno::BADR,           "ebadr"),
+    (Errno::BADRQC,         "ebadrqc"),
+    (Errno::BADSLT,         "ebadslt"),
+    (Errno::BFONT,          "ebfont"),
+    (Errno::CHRNG,          "echrng"),
+    (Errno::COMM,           "ecomm"),
+    (Errno::DOTDOT,         "edotdot"),
+    (Errno::HWPOISON,       "ehwpoison"),
+    (Errno::ISNAM,          "eisnam"),
+    (Errno::KEYEXPIRED,     "ekeyexpired"),
+    (Errno::KEYREJECTED,    "ekeyrejected"),
+    (Errno::KEYREVOKED,     "ekeyrevoked"),
+    (Errno::L2HLT,          "el2hlt"),
+    (Errno::L2NSYNC,        "el2nsync"),
+    (Errno::L3HLT,          "el3hlt"),
+    (Errno::L3RST,          "el3rst"),
+    (Errno::LIBACC,         "elibacc"),
+    (Errno::LIBBAD,         "elibbad"),
+    (Errno::LIBEXEC,        "elibexec"),
+    (Errno::LIBMAX,         "elibmax"),
+    (Errno::LIBSCN,         "elibscn"),
+    (Errno::LNRNG,          "elnrng"),
+    (Errno::MEDIUMTYPE,     "emediumtype"),
+    (Errno::MULTIHOP,       "emultihop"),
+    (Errno::NAVAIL,         "enavail"),
+    (Errno::NOANO,          "enoano"),
+    (Errno::NOCSI,          "enocsi"),
+    (Errno::NODATA,         "enodata"),
+    (Errno::NOKEY,          "enokey"),
+    (Errno::NOLINK,         "enolink"),
+    (Errno::NOMEDIUM,       "enomedium"),
+    (Errno::NONET,          "enonet"),
+    (Errno::NOPKG,          "enopkg"),
+    (Errno::NOSR,           "enosr"),
+    (Errno::NOSTR,          "enostr"),
+    (Errno::NOTNAM,         "enotnam"),
+    (Errno::NOTRECOVERABLE, "enotrecoverable"),
+    (Errno::NOTUNIQ,        "enotuniq"),
+    (Errno::OWNERDEAD,      "eownerdead"),
+    (Errno::REMCHG,         "eremchg"),
+    (Errno::REMOTEIO,       "eremoteio"),
+    (Errno::RESTART,        "erestart"),
+    (Errno::RFKILL,         "erfkill"),
+    (Errno::SRMNT,          "esrmnt"),
+    (Errno::STRPIPE,        "estrpipe"),
+    (Errno::TIME,           "etime"),
+    (Errno::UCLEAN,         "euclean"),
+    (Errno::UNATCH,         "eunatch"),
+    (Errno::XFULL,          "exfull"),
+];
+
+// There is no `ENODATA` here, `ENOATTR` takes its place and is normalized to `enodata`
+#[cfg(any(target_os = "freebsd", target_os = "macos", target_os = "netbsd"))]
+const PLATFORM_ERRNO_ATOMS: &[(Errno, &str)] = &[
+    (Errno::NOATTR, "enodata"),
+];
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "freebsd",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+)))]
+const PLATFORM_ERRNO_ATOMS: &[(Errno, &str)] = &[];
+
+// Sucess means it will be encoded as an atom (from static string)
+// Failure means it will be encoded as a string
+fn io_error_to_atom(err: &io::Error) -> Result<&'static str, String> {
+    if let Some(code) = err.raw_os_error() {
+        let errno = Errno::from_raw_os_error(code);
+        ERRNO_ATOMS
+            .iter()
+            .chain(PLATFORM_ERRNO_ATOMS)
+            .find(|(known, _)| *known == errno)
+            .map(|(_, atom_str)| *atom_str)
+            .ok_or_else(|| err.to_string())
+    } else if err.kind() == io::ErrorKind::Unsupported {
+        Ok("enotsup")
+    } else {
+        Err(err.to_string())
+    }
+}
+
@@ -0,0 +1,226 @@
+//! Finding files in a tree by a predicate over their attributes.
+//!
+//! Predicates are decoded from nested tuples once and evaluated natively against every
+//! walked file, so only the paths that match ever cross over to the BEAM. Only the
+//! attributes a predicate names are read, each at most once per file.
+//!
+//! ```elixir
+//! {:exists, name}
+//! {:missing, name}
+//! {:equals, name, value}
+//! {:prefix, name, prefix}
+//! {:regex, name, pattern}
+//! {:size, name, op, size}     # op is one of :<, :<=, :==, :!=, :>=, :>
+//! {:and, [predicate]}
+//! {:or, [predicate]}
+//! {:not, predicate}
+//! ```
+
+use std::ffi::OsString;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use regex::bytes::Regex;
+use rustler::types::tuple::get_tuple;
+use rustler::{Atom, Binary, Env, Error, NifResult, Term};
+
+use crate::decode;
+use crate::error::XattrError;
+use crate::tree::{Options, Walker};
+use crate::{as_path, to_binary};
+
+mod atoms {
+    rustler::atoms! {
+        exists,
+        missing,
+        equals,
+        prefix,
+        regex,
+        size,
+        and,
+        or,
+        not,
+        lt = "<",
+        le = "<=",
+        eq = "==",
+        ne = "!=",
+        ge = ">=",
+        gt = ">",
+    }
+}
+
+enum Compare {
+    Lt,
+    Le,
+    Eq,
+    Ne,
+    Ge,
+    Gt,
+}
+
+impl Compare {
+    fn decode(term: Term) -> NifResult<Self> {
+        let op: Atom = term.decode()?;
+        [
+            (atoms::lt(), Compare::Lt),
+            (atoms::le(), Compare::Le),
+            (atoms::eq(), Compare::Eq),
+            (atoms::ne(), Compare::Ne),
+            (atoms::ge(), Compare::Ge),
+            (atoms::gt(), Compare::Gt),
+        ]
+        .into_iter()
+        .find_map(|(atom, compare)| (atom == op).then_some(compare))
+        .ok_or(Error::BadArg)
+    }
+
+    fn apply(&self, left: usize, right: usize) -> bool {
+        match self {
+            Compare::Lt => left < right,
+            Compare::Le => left <= right,
+            Compare::Eq => left == right,
+            Compare::Ne => left != right,
+            Compare::Ge => left >= right,
+            Compare::Gt => left > right,
+        }
+    }
+}
+
+enum Predicate {
+    Exists(OsString),
+    Missing(OsString),
+    Equals(OsString, Vec<u8>),
+    Prefix(OsString, Vec<u8>),
+    Regex(OsString, Regex),
+    Size(OsString, Compare, usize),
+    And(Vec<Predicate>),
+    Or(Vec<Predicate>),
+    Not(Box<Predicate>),
+}
+
+impl Predicate {
+    fn decode(term: Term) -> NifResult<Self> {
+        let tuple = get_tuple(term)?;
+        let tag: Atom = tuple.first().ok_or(Error::BadArg)?.decode()?;
+
+        let predicate = match tuple[1..] {
+            [name] if tag == atoms::exists() => Predicate::Exists(decode::name(name)?),
+            [name] if tag == atoms::missing() => Predicate::Missing(decode::name(name)?),
+            [name, value] if tag == atoms::equals() => {
+                Predicate::Equals(decode::name(name)?, decode::bytes(value)?)
+            }
+            [name, prefix] if tag == atoms::prefix() => {
+                Predicate::Prefix(decode::name(name)?, decode::bytes(prefix)?)
+            }
+            [name, pattern] if tag == atoms::regex() => {
+                let pattern: &str = pattern.decode()?;
+                let regex = Regex::new(pattern).map_err(|_| Error::BadArg)?;
+                Predicate::Regex(decode::name(name)?, regex)
+            }
+            [name, op, size] if tag == atoms::size() => {
+                Predicate::Size(decode::name(name)?, Compare::decode(op)?, size.decode()?)
+            }
+            [predicates] if tag == atoms::and() => Predicate::And(Self::decode_all(predicates)?),
+            [predicates] if tag == atoms::or() => Predicate::Or(Self::decode_all(predicates)?),
+            [predicate] if tag == atoms::not() => {
+                Predicate::Not(Box::new(Self::decode(predicate)?))
+            }
+            _ => return Err(Error::BadArg),
+        };
+        Ok(predicate)
+    }
+
+    fn decode_all(term: Term) -> NifResult<Vec<Self>> {
+        term.decode::<Vec<Term>>()?.into_iter().map(Self::decode).collect()
+    }
+
+    fn eval(&self, attrs: &mut Attrs) -> Result<bool, XattrError> {
+        Ok(match self {
+            Predicate::Exists(name) => attrs.get(name)?.is_some(),
+            Predicate::Missing(name) => attrs.get(name)?.is_none(),
+            Predicate::Equals(name, value) => attrs.get(name)? == Some(value),
+            Predicate::Prefix(name, prefix) => {
+                attrs.get(name)?.is_some_and(|value| value.starts_with(prefix))
+            }
+            Predicate::Regex(name, regex) => {
+                attrs.get(name)?.is_some_and(|value| regex.is_match(value))
+            }
+            Predicate::Size(name, compare, size) => {
+                attrs.get(name)?.is_some_and(|value| compare.apply(value.len(), *size))
+            }
+            Predicate::And(predicates) => {
+                for predicate in predicates {
+                    if !predicate.eval(attrs)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Predicate::Or(predicates) => {
+                for predicate in predicates {
+                    if predicate.eval(attrs)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+            Predicate::Not(predicate) => !predicate.eval(attrs)?,
+        })
+    }
+}
+
+// The attributes of one file, read on demand
+struct Attrs<'a> {
+    path: &'a Path,
+    follow: bool,
+    // Predicates rarely name more than a handful of attributes, a list beats hashing
+    read: Vec<(OsString, Option<Vec<u8>>)>,
+}
+
+impl<'a> Attrs<'a> {
+    fn new(path: &'a Path, follow: bool) -> Self {
+        Attrs { path, follow, read: Vec::new() }
+    }
+
+    fn get(&mut self, name: &OsString) -> Result<Option<&Vec<u8>>, XattrError> {
+        let index = match self.read.iter().position(|(read, _)| read == name) {
+            Some(index) => index,
+            None => {
+                let (value, syscall) = if self.follow {
+                    (xattr::get_deref(self.path, name), "getxattr")
+                } else {
+                    (xattr::get(self.path, name), "lgetxattr")
+                };
+                let value = value.map_err(|e| XattrError::new(e, syscall).with_name(name))?;
+                self.read.push((name.clone(), value));
+                self.read.len() - 1
+            }
+        };
+        Ok(self.read[index].1.as_ref())
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn find_xattr<'a>(
+    env: Env<'a>,
+    root: Binary,
+    predicate: Term,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+) -> NifResult<Vec<Binary<'a>>> {
+    let predicate = Predicate::decode(predicate)?;
+    let options = Options { max_depth, follow, cross_mounts };
+
+    let mut found = Vec::new();
+    for path in Walker::new(as_path(&root).to_owned(), options) {
+        let path = path?;
+        match predicate.eval(&mut Attrs::new(&path, follow)) {
+            Ok(true) => found.push(to_binary(env, path.as_os_str().as_bytes())?),
+            Ok(false) => (),
+            Err(e) if e.is_vanished() => (),
+            Err(e) => return Err(e.with_path(&path).into()),
+        }
+    }
+    Ok(found)
+}
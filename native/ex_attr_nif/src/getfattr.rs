@@ -0,0 +1,201 @@
+//! The text format of `getfattr --dump` and `setfattr --restore`.
+//!
+//! ```text
+//! # file: data/a.txt
+//! user.checksum="d41d8cd9"
+//! user.blob=0sAAECAw==
+//!
+//! ```
+//!
+//! Every file gets a `# file:` header followed by one `name=value` line per attribute and
+//! a blank line. Paths and names escape `\` and line breaks (and `=` in names) as `\ooo`
+//! octal sequences. Values are either `"text"` with the same escapes, `0x` followed by hex
+//! or `0s` followed by base64. An empty value is written as the bare name.
+//!
+//! Unlike getfattr, a trailing NUL byte in a text value is kept rather than dropped, so
+//! every value round-trips exactly.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use rustler::types::atom;
+use rustler::{Atom, Binary, Env, NifResult};
+
+use crate::dump::Entries;
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::snapshot::{self, Files};
+use crate::tree::{self, Options, Walker};
+use crate::{as_path, to_binary};
+
+// How values are written, the same choices as `getfattr --encoding`
+#[derive(Clone, Copy, rustler::NifUnitEnum)]
+pub enum Encoding {
+    Text,
+    Hex,
+    Base64,
+}
+
+impl Encoding {
+    // What getfattr picks when no encoding is given: text, unless more than an eighth of
+    // the bytes aren't printable
+    fn guess(value: &[u8]) -> Self {
+        let unprintable = value.iter().filter(|&&byte| !is_print(byte)).count();
+        if value.len() >= unprintable * 8 {
+            Encoding::Text
+        } else {
+            Encoding::Base64
+        }
+    }
+}
+
+// `isprint` in the C locale
+fn is_print(byte: u8) -> bool {
+    (0x20..0x7f).contains(&byte)
+}
+
+fn push_octal(out: &mut Vec<u8>, byte: u8) {
+    let digits = [byte >> 6, (byte >> 3) & 7, byte & 7].map(|digit| b'0' + digit);
+    out.push(b'\\');
+    out.extend_from_slice(&digits);
+}
+
+fn quote(out: &mut Vec<u8>, bytes: &[u8], special: &[u8]) {
+    for &byte in bytes {
+        if byte == b'\\' || special.contains(&byte) {
+            push_octal(out, byte);
+        } else {
+            out.push(byte);
+        }
+    }
+}
+
+// Turns every `\ooo` sequence back into its byte, anything else is kept as is
+fn unquote(bytes: &[u8]) -> Vec<u8> {
+    let is_octal = |byte: Option<&u8>| byte.is_some_and(|byte| (b'0'..=b'7').contains(byte));
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && (1..=3).all(|offset| is_octal(bytes.get(i + offset))) {
+            let digits = &bytes[i + 1..i + 4];
+            out.push((digits[0] - b'0') << 6 | (digits[1] - b'0') << 3 | (digits[2] - b'0'));
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    out
+}
+
+fn encode_value(out: &mut Vec<u8>, value: &[u8], encoding: Option<Encoding>) {
+    match encoding.unwrap_or_else(|| Encoding::guess(value)) {
+        Encoding::Text => {
+            out.push(b'"');
+            quote(out, value, b"\0\n\r\"");
+            out.push(b'"');
+        }
+        Encoding::Hex => {
+            out.extend_from_slice(b"0x");
+            for byte in value {
+                out.extend_from_slice(format!("{byte:02x}").as_bytes());
+            }
+        }
+        Encoding::Base64 => {
+            out.extend_from_slice(b"0s");
+            out.extend_from_slice(BASE64.encode(value).as_bytes());
+        }
+    }
+}
+
+// Same rules as setfattr, which also accepts unquoted text
+fn decode_value(value: &[u8]) -> Option<Vec<u8>> {
+    match value {
+        [b'0', b'x' | b'X', hex @ ..] => {
+            let digits = hex.iter().filter(|byte| !byte.is_ascii_whitespace()).copied();
+            let digits = digits.collect::<Vec<_>>();
+            if digits.len() % 2 != 0 {
+                return None;
+            }
+            let nibble = |digit: u8| (digit as char).to_digit(16).map(|nibble| nibble as u8);
+            digits.chunks(2).map(|pair| Some(nibble(pair[0])? << 4 | nibble(pair[1])?)).collect()
+        }
+        [b'0', b's' | b'S', base64 @ ..] => {
+            let base64 = base64.iter().filter(|byte| !byte.is_ascii_whitespace()).copied();
+            BASE64.decode(base64.collect::<Vec<_>>()).ok()
+        }
+        [b'"', text @ .., b'"'] => Some(unquote(text)),
+        text => Some(unquote(text)),
+    }
+}
+
+fn export_entry(out: &mut Vec<u8>, path: &Path, entries: Entries, encoding: Option<Encoding>) {
+    out.extend_from_slice(b"# file: ");
+    quote(out, path.as_os_str().as_bytes(), b"\n\r");
+    out.push(b'\n');
+    for (name, value) in entries {
+        quote(out, name.as_bytes(), b"=\n\r");
+        if !value.is_empty() {
+            out.push(b'=');
+            encode_value(out, &value, encoding);
+        }
+        out.push(b'\n');
+    }
+    out.push(b'\n');
+}
+
+fn parse(dump: &[u8]) -> Result<Files, XattrError> {
+    let mut files: Files = Vec::new();
+    for (index, line) in dump.split(|&byte| byte == b'\n').enumerate() {
+        if let Some(path) = line.strip_prefix(b"# file: ") {
+            files.push((PathBuf::from(OsStr::from_bytes(&unquote(path))), Vec::new()));
+            continue;
+        }
+        if line.is_empty() || line.starts_with(b"#") {
+            continue;
+        }
+
+        let invalid = || XattrError::invalid_dump(index + 1);
+        let (_, entries) = files.last_mut().ok_or_else(invalid)?;
+        let (name, value) = match line.iter().position(|&byte| byte == b'=') {
+            Some(split) => (&line[..split], decode_value(&line[split + 1..]).ok_or_else(invalid)?),
+            None => (line, Vec::new()),
+        };
+        entries.push((OsStr::from_bytes(&unquote(name)).to_owned(), value));
+    }
+    Ok(files)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn export_xattr<'a>(
+    env: Env<'a>,
+    root: Binary,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+    encoding: Option<Encoding>,
+) -> NifResult<Binary<'a>> {
+    let options = Options { max_depth, follow, cross_mounts };
+    let prefix = Prefix::new(prefix.as_ref(), false);
+
+    let mut out = Vec::new();
+    for path in Walker::new(as_path(&root).to_owned(), options) {
+        let path = path?;
+        if let Some(entries) = tree::dump_entry(&path, follow, prefix)? {
+            export_entry(&mut out, &path, entries, encoding);
+        }
+    }
+    to_binary(env, &out)
+}
+
+// The whole dump is parsed and checked before any attribute is set, so a malformed one
+// changes nothing
+#[rustler::nif(schedule = "DirtyIo")]
+fn import_xattr(dump: Binary, follow: bool) -> NifResult<Atom> {
+    snapshot::apply(&parse(dump.as_slice())?, follow)?;
+    Ok(atom::ok())
+}
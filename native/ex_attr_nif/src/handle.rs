@@ -0,0 +1,97 @@
+//! Open file handles that attributes can be managed through repeatedly.
+//!
+//! The path is only resolved once when the handle is opened, every operation after that
+//! goes through the descriptor via `xattr::FileExt`, so it keeps applying to the same
+//! file even if it gets renamed or replaced. The descriptor is closed once the handle is
+//! garbage collected.
+
+use std::fs::File;
+
+use rustler::types::atom;
+use rustler::{Atom, Binary, Env, NifResult, ResourceArc, Term};
+use xattr::FileExt;
+
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::{as_os_str, as_path, dump, sys, to_binary, to_map, to_names, validate, SetMode};
+
+pub struct Handle {
+    file: File,
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn open_handle(path: Binary, follow: bool) -> NifResult<ResourceArc<Handle>> {
+    match sys::open(as_path(&path), follow) {
+        Ok(file) => Ok(ResourceArc::new(Handle { file })),
+        Err(e) => Err(XattrError::new(e, "open").into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn handle_get_xattr<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<Handle>,
+    name: Binary,
+) -> NifResult<Option<Binary<'a>>> {
+    let name = as_os_str(&name);
+    validate::name(name)?;
+    match handle.file.get_xattr(name) {
+        Ok(Some(value)) => Ok(Some(to_binary(env, &value)?)),
+        Ok(None) => Ok(None),
+        Err(e) => Err(XattrError::new(e, "fgetxattr").with_name(name).into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn handle_set_xattr(
+    handle: ResourceArc<Handle>,
+    name: Binary,
+    value: Binary,
+    mode: SetMode,
+) -> NifResult<Atom> {
+    let (name, value) = (as_os_str(&name), value.as_slice());
+    validate::name(name)?;
+    validate::value(name, value)?;
+    match sys::fset(&handle.file, name, value, mode) {
+        Ok(_) => Ok(atom::ok()),
+        Err(e) => Err(XattrError::new(e, "fsetxattr").with_name(name).into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn handle_list_xattr<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<Handle>,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> NifResult<Vec<Term<'a>>> {
+    match handle.file.list_xattr() {
+        Ok(attrs) => to_names(env, attrs, Prefix::new(prefix.as_ref(), strip), parse),
+        Err(e) => Err(XattrError::new(e, "flistxattr").into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn handle_remove_xattr(handle: ResourceArc<Handle>, name: Binary) -> NifResult<Atom> {
+    let name = as_os_str(&name);
+    validate::name(name)?;
+    match handle.file.remove_xattr(name) {
+        Ok(_) => Ok(atom::ok()),
+        Err(e) => Err(XattrError::new(e, "fremovexattr").with_name(name).into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn handle_dump_xattr<'a>(
+    env: Env<'a>,
+    handle: ResourceArc<Handle>,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> NifResult<Term<'a>> {
+    match dump::dump_file(&handle.file, Prefix::new(prefix.as_ref(), strip)) {
+        Ok(entries) => to_map(env, entries, parse),
+        Err(e) => Err(e.into()),
+    }
+}
@@ -1,31 +1,102 @@
-use rustler::{Atom, Error, NifResult, Binary};
+use rustler::{Atom, Binary, Env, NifResult, OwnedBinary, Term};
 use rustler::types::atom;
 use rustix::io::Errno;
-use std::io;
-
-// Sucess means it will be encoded as an atom (from static string)
-// Failure means it will be encoded as a string 
-fn io_error_to_atom(err: io::Error) -> Result<&'static str, String> {
-    if let Some(code) = err.raw_os_error() {
-        match Errno::from_raw_os_error(code) {
-            Errno::TOOBIG => Ok("e2big"),
-            Errno::ACCESS => Ok("eacces"),
-            Errno::INVAL  => Ok("einval"),
-            Errno::IO     => Ok("eio"),
-            Errno::NODATA => Ok("enodata"),
-            Errno::NOENT  => Ok("enoent"),
-            Errno::NOMEM  => Ok("enomem"),
-            Errno::NOSPC  => Ok("enospc"),
-            Errno::PERM   => Ok("eperm"),
-            Errno::ROFS   => Ok("erofs"),
-            Errno::NOTSUP => Ok("enotsup"),
-            _ => Err(err.to_string()),
-        }
-    } else if err.kind() == io::ErrorKind::Unsupported {
-        Ok("enotsup")
-    } else {
-        Err(err.to_string())
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+mod archive;
+mod copy;
+mod cp;
+mod decode;
+mod diff;
+mod dump;
+mod error;
+mod find;
+mod getfattr;
+mod handle;
+mod name;
+mod prefix;
+mod scan;
+mod snapshot;
+mod stream;
+mod sys;
+mod temp;
+mod tree;
+mod validate;
+mod write;
+
+use archive::{create_tar_xattr, extract_tar_xattr, list_tar_xattr};
+use copy::copy_xattr;
+use cp::cp_xattr;
+use diff::{diff_xattr, patch_xattr};
+use error::XattrError;
+use find::find_xattr;
+use getfattr::{export_xattr, import_xattr};
+use name::{format_name, parse_name};
+use prefix::Prefix;
+use scan::{ack_tree_scan, start_tree_scan, TreeScan};
+use snapshot::{restore_xattr, snapshot_xattr};
+use stream::{close_tree_stream, next_tree_stream, open_tree_stream, TreeStream};
+use tree::dump_tree_xattr;
+use write::write_xattr;
+use handle::{
+    handle_dump_xattr,
+    handle_get_xattr,
+    handle_list_xattr,
+    handle_remove_xattr,
+    handle_set_xattr,
+    open_handle,
+    Handle,
+};
+
+// Paths and names cross the NIF boundary as raw bytes so that anything the filesystem
+// accepts can be used, not only valid UTF-8
+fn as_os_str<'a>(binary: &'a Binary) -> &'a OsStr {
+    OsStr::from_bytes(binary.as_slice())
+}
+
+fn as_path<'a>(binary: &'a Binary) -> &'a Path {
+    Path::new(as_os_str(binary))
+}
+
+// Copies raw bytes into a freshly allocated erlang binary, rustler would otherwise
+// encode a `Vec<u8>` as a list of integers
+fn to_binary<'a>(env: Env<'a>, bytes: &[u8]) -> NifResult<Binary<'a>> {
+    let mut binary = OwnedBinary::new(bytes.len())
+        .ok_or_else(|| XattrError::from(std::io::Error::from(Errno::NOMEM)))?;
+    binary.as_mut_slice().copy_from_slice(bytes);
+    Ok(binary.release(env))
+}
+
+fn to_names<'a>(
+    env: Env<'a>,
+    names: xattr::XAttrs,
+    prefix: Prefix,
+    parse: bool,
+) -> NifResult<Vec<Term<'a>>> {
+    names
+        .filter_map(|name| Some(name::encode(env, prefix.apply(&name)?, parse)))
+        .collect()
+}
+
+fn to_map<'a>(env: Env<'a>, entries: dump::Entries, parse: bool) -> NifResult<Term<'a>> {
+    let mut map = Term::map_new(env);
+    for (name, value) in entries {
+        let name = name::encode(env, &name, parse)?;
+        let value = to_binary(env, &value)?;
+        map = map.map_put(name, value)?;
     }
+    Ok(map)
+}
+
+// How `set_xattr` should treat an attribute that may or may not already exist, mapped to
+// the `XATTR_CREATE` and `XATTR_REPLACE` flags of setxattr(2)
+#[derive(Clone, Copy, rustler::NifUnitEnum)]
+pub enum SetMode {
+    Create,
+    Replace,
+    Upsert,
 }
 
 #[rustler::nif]
@@ -33,57 +104,184 @@ fn supported_platform() -> bool {
     xattr::SUPPORTED_PLATFORM
 }
 
-#[rustler::nif]
-fn get_xattr(path: String, name: String) -> NifResult<Option<Vec<u8>>> {
-    match xattr::get(path, name) {
-        Ok(Some(value)) => Ok(Some(value)),
+// Everything below touches the filesystem and can block for a long time on NFS, FUSE
+// or a slow disk, so it all runs on the dirty I/O schedulers.
+//
+// The `follow` flag picks between the `l*xattr` syscalls which operate on a symlink
+// itself and the plain ones which operate on the symlink's target.
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn get_xattr<'a>(
+    env: Env<'a>,
+    path: Binary,
+    name: Binary,
+    follow: bool,
+) -> NifResult<Option<Binary<'a>>> {
+    let (path, name) = (as_path(&path), as_os_str(&name));
+    validate::name(name)?;
+    let syscall = if follow { "getxattr" } else { "lgetxattr" };
+    let result = if follow { xattr::get_deref(path, name) } else { xattr::get(path, name) };
+
+    match result {
+        Ok(Some(value)) => Ok(Some(to_binary(env, &value)?)),
         Ok(None) => Ok(None),
-        Err(e) => match io_error_to_atom(e) {
-            Ok(atom_str) => Err(Error::Atom(atom_str)),
-            Err(msg) => Err(Error::Term(Box::new(msg))),
-        },
+        Err(e) => Err(XattrError::new(e, syscall).with_name(name).into()),
     }
 }
 
-#[rustler::nif]
-fn set_xattr(path: String, name: String, value: Binary) -> NifResult<Atom> {
-    match xattr::set(path, name, value.as_slice()) {
+#[rustler::nif(schedule = "DirtyIo")]
+fn set_xattr(
+    path: Binary,
+    name: Binary,
+    value: Binary,
+    follow: bool,
+    mode: SetMode,
+) -> NifResult<Atom> {
+    let (path, name, value) = (as_path(&path), as_os_str(&name), value.as_slice());
+    validate::name(name)?;
+    validate::value(name, value)?;
+    let syscall = if follow { "setxattr" } else { "lsetxattr" };
+    let result = match mode {
+        // Plain upserts stay on the `xattr` crate since it also supports the BSDs
+        SetMode::Upsert if follow => xattr::set_deref(path, name, value),
+        SetMode::Upsert => xattr::set(path, name, value),
+        _ => sys::set(path, name, value, follow, mode),
+    };
+
+    match result {
         Ok(_) => Ok(atom::ok()),
-        Err(e) => match io_error_to_atom(e) {
-            Ok(atom_str) => Err(Error::Atom(atom_str)),
-            Err(msg) => Err(Error::Term(Box::new(msg))),
-        },
+        Err(e) => Err(XattrError::new(e, syscall).with_name(name).into()),
     }
 }
 
-#[rustler::nif]
-fn list_xattr(path: String) -> NifResult<Vec<String>> {
-    match xattr::list(path) {
-        Ok(attrs) => attrs.map(|attr| {
-            attr.into_string().map_err(|_| Error::Term(Box::new("Failed to convert OsString".to_string())))
-        }).collect(),
-        Err(e) => match io_error_to_atom(e) {
-            Ok(atom_str) => Err(Error::Atom(atom_str)),
-            Err(msg) => Err(Error::Term(Box::new(msg))),
-        },
+#[rustler::nif(schedule = "DirtyIo")]
+fn list_xattr<'a>(
+    env: Env<'a>,
+    path: Binary,
+    follow: bool,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> NifResult<Vec<Term<'a>>> {
+    let path = as_path(&path);
+    let syscall = if follow { "listxattr" } else { "llistxattr" };
+    let result = if follow { xattr::list_deref(path) } else { xattr::list(path) };
+
+    match result {
+        Ok(attrs) => to_names(env, attrs, Prefix::new(prefix.as_ref(), strip), parse),
+        Err(e) => Err(XattrError::new(e, syscall).into()),
     }
 }
 
-#[rustler::nif]
-fn remove_xattr(path: String, name: String) -> NifResult<Atom> {
-    match xattr::remove(path, name) {
+#[rustler::nif(schedule = "DirtyIo")]
+fn remove_xattr(path: Binary, name: Binary, follow: bool) -> NifResult<Atom> {
+    let (path, name) = (as_path(&path), as_os_str(&name));
+    validate::name(name)?;
+    let syscall = if follow { "removexattr" } else { "lremovexattr" };
+    let result = if follow { xattr::remove_deref(path, name) } else { xattr::remove(path, name) };
+
+    match result {
         Ok(_) => Ok(atom::ok()),
-        Err(e) => match io_error_to_atom(e) {
-            Ok(atom_str) => Err(Error::Atom(atom_str)),
-            Err(msg) => Err(Error::Term(Box::new(msg))),
-        },
+        Err(e) => Err(XattrError::new(e, syscall).with_name(name).into()),
+    }
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn dump_xattr<'a>(
+    env: Env<'a>,
+    path: Binary,
+    follow: bool,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> NifResult<Term<'a>> {
+    match dump::dump(as_path(&path), follow, Prefix::new(prefix.as_ref(), strip)) {
+        Ok(entries) => to_map(env, entries, parse),
+        Err(e) => Err(e.into()),
     }
 }
 
+#[rustler::nif(schedule = "DirtyIo")]
+fn size_xattr(path: Binary, name: Binary, follow: bool) -> NifResult<Option<usize>> {
+    let (path, name) = (as_path(&path), as_os_str(&name));
+    validate::name(name)?;
+    let syscall = if follow { "getxattr" } else { "lgetxattr" };
+
+    match sys::size(path, name, follow) {
+        Ok(size) => Ok(size),
+        Err(e) => Err(XattrError::new(e, syscall).with_name(name).into()),
+    }
+}
+
+// Attributes removed between being listed and being sized are skipped
+#[rustler::nif(schedule = "DirtyIo")]
+fn list_sizes_xattr<'a>(
+    env: Env<'a>,
+    path: Binary,
+    follow: bool,
+) -> NifResult<Vec<(Binary<'a>, usize)>> {
+    let path = as_path(&path);
+    let (list_syscall, get_syscall) = if follow {
+        ("listxattr", "getxattr")
+    } else {
+        ("llistxattr", "lgetxattr")
+    };
+    let list = if follow { xattr::list_deref(path) } else { xattr::list(path) };
+
+    let mut sizes = Vec::new();
+    for name in list.map_err(|e| XattrError::new(e, list_syscall))? {
+        match sys::size(path, &name, follow) {
+            Ok(Some(size)) => sizes.push((to_binary(env, name.as_bytes())?, size)),
+            Ok(None) => (),
+            Err(e) => return Err(XattrError::new(e, get_syscall).with_name(&name).into()),
+        }
+    }
+    Ok(sizes)
+}
+
+// `resource!` from this version of rustler still implements its trait inside the function
+#[allow(non_local_definitions)]
+fn load(env: Env, _info: Term) -> bool {
+    rustler::resource!(Handle, env);
+    rustler::resource!(TreeScan, env);
+    rustler::resource!(TreeStream, env);
+    true
+}
+
 rustler::init!("Elixir.ExAttr.Nif", [
     supported_platform,
     get_xattr,
     set_xattr,
     list_xattr,
     remove_xattr,
-]);
+    dump_xattr,
+    size_xattr,
+    list_sizes_xattr,
+    open_handle,
+    handle_get_xattr,
+    handle_set_xattr,
+    handle_list_xattr,
+    handle_remove_xattr,
+    handle_dump_xattr,
+    parse_name,
+    format_name,
+    dump_tree_xattr,
+    start_tree_scan,
+    ack_tree_scan,
+    open_tree_stream,
+    next_tree_stream,
+    close_tree_stream,
+    find_xattr,
+    copy_xattr,
+    diff_xattr,
+    patch_xattr,
+    export_xattr,
+    import_xattr,
+    snapshot_xattr,
+    restore_xattr,
+    list_tar_xattr,
+    extract_tar_xattr,
+    create_tar_xattr,
+    cp_xattr,
+    write_xattr,
+], load = load);
@@ -0,0 +1,93 @@
+//! Attribute names split into their namespace and key, mirrored by `ExAttr.Name`.
+//!
+//! Parsing and formatting live here so that `ExAttr.Name.parse/1`, `ExAttr.Name.format/1`
+//! and the structured names returned by listing and dumping all agree on where the
+//! namespace ends.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use rustler::{Binary, Encoder, Env, NifResult, Term};
+
+use crate::{as_os_str, to_binary, validate};
+
+#[derive(Clone, Copy, PartialEq, rustler::NifUnitEnum)]
+pub enum Namespace {
+    Security,
+    System,
+    Trusted,
+    User,
+}
+
+impl Namespace {
+    pub fn from_bytes(namespace: &[u8]) -> Option<Self> {
+        match namespace {
+            b"security" => Some(Namespace::Security),
+            b"system" => Some(Namespace::System),
+            b"trusted" => Some(Namespace::Trusted),
+            b"user" => Some(Namespace::User),
+            _ => None,
+        }
+    }
+
+    fn as_bytes(self) -> &'static [u8] {
+        match self {
+            Namespace::Security => b"security",
+            Namespace::System => b"system",
+            Namespace::Trusted => b"trusted",
+            Namespace::User => b"user",
+        }
+    }
+}
+
+// `namespace` is `nil` on macOS, where names have no namespaces and the key is the
+// whole name
+#[derive(rustler::NifStruct)]
+#[module = "ExAttr.Name"]
+pub struct Name<'a> {
+    namespace: Option<Namespace>,
+    key: Binary<'a>,
+}
+
+// Splits a name that has already been validated, or that came from the kernel
+pub fn split(name: &[u8]) -> (Option<Namespace>, &[u8]) {
+    if cfg!(target_os = "macos") {
+        return (None, name);
+    }
+    match name.iter().position(|&b| b == b'.') {
+        Some(dot) => match Namespace::from_bytes(&name[..dot]) {
+            Some(namespace) => (Some(namespace), &name[dot + 1..]),
+            None => (None, name),
+        },
+        None => (None, name),
+    }
+}
+
+// Encodes a name listed by the kernel, either as is or as an `ExAttr.Name` struct
+pub fn encode<'a>(env: Env<'a>, name: &OsStr, parse: bool) -> NifResult<Term<'a>> {
+    if !parse {
+        return Ok(to_binary(env, name.as_bytes())?.encode(env));
+    }
+    let (namespace, key) = split(name.as_bytes());
+    let key = to_binary(env, key)?;
+    Ok(Name { namespace, key }.encode(env))
+}
+
+#[rustler::nif]
+fn parse_name<'a>(env: Env<'a>, name: Binary) -> NifResult<Name<'a>> {
+    let name = as_os_str(&name);
+    validate::name(name)?;
+    let (namespace, key) = split(name.as_bytes());
+    Ok(Name { namespace, key: to_binary(env, key)? })
+}
+
+#[rustler::nif]
+fn format_name<'a>(env: Env<'a>, name: Name) -> NifResult<Binary<'a>> {
+    let key = name.key.as_slice();
+    let name = match name.namespace {
+        Some(namespace) => [namespace.as_bytes(), b".", key].concat(),
+        None => key.to_vec(),
+    };
+    validate::name(OsStr::from_bytes(&name))?;
+    to_binary(env, &name)
+}
@@ -0,0 +1,31 @@
+//! Name prefix filtering shared by listing and dumping.
+//!
+//! Filtering happens natively so names that are thrown away never have to be copied over
+//! to the BEAM, and when dumping their values are never even read.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use rustler::Binary;
+
+#[derive(Clone, Copy)]
+pub struct Prefix<'a> {
+    prefix: &'a [u8],
+    strip: bool,
+}
+
+impl<'a> Prefix<'a> {
+    pub fn new(prefix: Option<&'a Binary>, strip: bool) -> Self {
+        Prefix::from_bytes(prefix.map_or(&[][..], |prefix| prefix.as_slice()), strip)
+    }
+
+    pub fn from_bytes(prefix: &'a [u8], strip: bool) -> Self {
+        Prefix { prefix, strip }
+    }
+
+    // The name to report if it starts with the prefix, stripped if asked to
+    pub fn apply<'n>(&self, name: &'n OsStr) -> Option<&'n OsStr> {
+        let rest = name.as_bytes().strip_prefix(self.prefix)?;
+        Some(if self.strip { OsStr::from_bytes(rest) } else { name })
+    }
+}
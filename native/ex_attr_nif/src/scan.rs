@@ -0,0 +1,352 @@
+//! Tree walks spread over a pool of native threads.
+//!
+//! Directories are shared between the workers through a queue, each worker reading one
+//! directory at a time and queueing the subdirectories it finds. Results are batched and
+//! handed to a single sender thread which messages them to the calling process as
+//! `{tag, {:batch, entries}}`, followed by either `{tag, :done}` or
+//! `{tag, {:error, details}}`.
+//!
+//! The caller has to acknowledge every batch with `ack_tree_scan/1`. Only a few batches
+//! are ever in flight, after that the sender waits for acknowledgements and the workers
+//! block once the channel to it fills up, so a slow caller never gets flooded. The scan is
+//! cancelled once its resource is garbage collected, which includes the caller exiting.
+
+use std::collections::HashSet;
+use std::fs::Metadata;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::{mem, thread};
+
+use rustler::env::{OwnedEnv, SavedTerm, SendError};
+use rustler::types::atom;
+use rustler::{Atom, Binary, Encoder, Env, LocalPid, NifResult, ResourceArc, Term};
+use rustix::io::Errno;
+
+use crate::dump;
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::tree::{self, Options};
+use crate::{as_path, to_binary, to_map};
+
+mod atoms {
+    rustler::atoms! {
+        batch,
+        done,
+    }
+}
+
+// Batches sent to the caller that it hasn't acknowledged yet
+const WINDOW: usize = 2;
+
+type Batch = Vec<(PathBuf, dump::Entries)>;
+
+struct Queue {
+    // Directories left to read along with their depth
+    dirs: Vec<(PathBuf, usize)>,
+    // Workers currently reading a directory, which may queue more
+    busy: usize,
+}
+
+struct Shared {
+    options: Options,
+    prefix: Vec<u8>,
+    strip: bool,
+    batch_size: usize,
+    root_dev: u64,
+    queue: Mutex<Queue>,
+    queue_changed: Condvar,
+    // Directories already descended into, only tracked when following symlinks
+    visited: Mutex<HashSet<(u64, u64)>>,
+    credits: Mutex<usize>,
+    credits_changed: Condvar,
+    cancelled: AtomicBool,
+}
+
+impl Shared {
+    fn prefix(&self) -> Prefix<'_> {
+        Prefix::from_bytes(&self.prefix, self.strip)
+    }
+
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+        // Taking the locks makes sure no waiter misses the flag between checking it and
+        // going to sleep
+        drop(self.queue.lock().unwrap());
+        self.queue_changed.notify_all();
+        drop(self.credits.lock().unwrap());
+        self.credits_changed.notify_all();
+    }
+
+    // The next directory to read, `None` once every directory has been read
+    fn next_dir(&self) -> Option<(PathBuf, usize)> {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            if self.is_cancelled() {
+                return None;
+            }
+            if let Some(dir) = queue.dirs.pop() {
+                queue.busy += 1;
+                return Some(dir);
+            }
+            if queue.busy == 0 {
+                return None;
+            }
+            queue = self.queue_changed.wait(queue).unwrap();
+        }
+    }
+
+    fn push_dir(&self, dir: PathBuf, depth: usize) {
+        self.queue.lock().unwrap().dirs.push((dir, depth));
+        self.queue_changed.notify_one();
+    }
+
+    fn finish_dir(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        queue.busy -= 1;
+        if queue.busy == 0 && queue.dirs.is_empty() {
+            self.queue_changed.notify_all();
+        }
+    }
+
+    fn descends(&self, metadata: &Metadata, depth: usize) -> bool {
+        self.options.descends(metadata, depth, self.root_dev)
+            && (!self.options.follow
+                || self.visited.lock().unwrap().insert((metadata.dev(), metadata.ino())))
+    }
+
+    // Waits until the caller can take another batch, false if cancelled in the meantime
+    fn take_credit(&self) -> bool {
+        let mut credits = self.credits.lock().unwrap();
+        while *credits == 0 && !self.is_cancelled() {
+            credits = self.credits_changed.wait(credits).unwrap();
+        }
+        if self.is_cancelled() {
+            return false;
+        }
+        *credits -= 1;
+        true
+    }
+
+    fn give_credit(&self) {
+        *self.credits.lock().unwrap() += 1;
+        self.credits_changed.notify_one();
+    }
+}
+
+pub struct TreeScan {
+    shared: Arc<Shared>,
+}
+
+impl Drop for TreeScan {
+    fn drop(&mut self) {
+        self.shared.cancel();
+    }
+}
+
+fn worker(shared: Arc<Shared>, tx: SyncSender<Result<Batch, XattrError>>) {
+    let mut batch = Vec::new();
+    while let Some((dir, depth)) = shared.next_dir() {
+        let result = scan_dir(&shared, &dir, depth + 1, &mut batch, &tx);
+        shared.finish_dir();
+        // The sender cancels the scan once it gets to the error, cancelling here could
+        // leave it waiting on batches that are never sent
+        if let Err(e) = result {
+            let _ = tx.send(Err(e));
+            return;
+        }
+    }
+    if !batch.is_empty() {
+        let _ = tx.send(Ok(batch));
+    }
+}
+
+// Visits every entry of `dir`, which are at `depth`
+fn scan_dir(
+    shared: &Shared,
+    dir: &Path,
+    depth: usize,
+    batch: &mut Batch,
+    tx: &SyncSender<Result<Batch, XattrError>>,
+) -> Result<(), XattrError> {
+    let follow = shared.options.follow;
+    let names = tree::read_dir(dir).map_err(|e| e.with_path(dir))?;
+
+    for name in names {
+        if shared.is_cancelled() {
+            return Ok(());
+        }
+        let path = dir.join(name);
+        let metadata = match tree::metadata(&path, follow) {
+            Ok(metadata) => metadata,
+            Err(e) if e.is_vanished() => continue,
+            Err(e) => return Err(e.with_path(&path)),
+        };
+        if shared.descends(&metadata, depth) {
+            shared.push_dir(path.clone(), depth);
+        }
+        if let Some(entries) = tree::dump_entry(&path, follow, shared.prefix())? {
+            batch.push((path, entries));
+            if batch.len() >= shared.batch_size && tx.send(Ok(mem::take(batch))).is_err() {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+struct Outbox {
+    env: OwnedEnv,
+    pid: LocalPid,
+    // The caller's tag lives in its own env since `env` is cleared after every message
+    tag_env: OwnedEnv,
+    tag: SavedTerm,
+}
+
+impl Outbox {
+    fn send<F>(&mut self, message: F) -> Result<(), SendError>
+    where
+        F: for<'a> FnOnce(Env<'a>) -> Term<'a>,
+    {
+        let (tag_env, tag) = (&self.tag_env, &self.tag);
+        self.env.send_and_clear(&self.pid, |env| {
+            let tag = tag_env.run(|tag_env| tag.load(tag_env).in_env(env));
+            (tag, message(env))
+        })
+    }
+}
+
+fn deliver(
+    shared: Arc<Shared>,
+    rx: Receiver<Result<Batch, XattrError>>,
+    mut outbox: Outbox,
+    parse: bool,
+) {
+    for batch in rx {
+        let sent = match batch {
+            Ok(batch) => {
+                if !shared.take_credit() {
+                    return;
+                }
+                let mut failed = false;
+                let sent = outbox.send(|env| match encode_batch(env, batch, parse) {
+                    Ok(batch) => (atoms::batch(), batch).encode(env),
+                    Err(e) => {
+                        failed = true;
+                        (atom::error(), e).encode(env)
+                    }
+                });
+                // Same as an error from the workers, nothing else follows it
+                if failed {
+                    shared.cancel();
+                    return;
+                }
+                sent
+            }
+            Err(e) => {
+                let _ = outbox.send(|env| (atom::error(), e).encode(env));
+                shared.cancel();
+                return;
+            }
+        };
+        // The caller is gone, no point in scanning any further
+        if sent.is_err() {
+            shared.cancel();
+            return;
+        }
+    }
+    if !shared.is_cancelled() {
+        let _ = outbox.send(|env| atoms::done().encode(env));
+    }
+}
+
+fn encode_batch(env: Env, batch: Batch, parse: bool) -> Result<Term, XattrError> {
+    let nomem = |_| XattrError::from(io::Error::from(Errno::NOMEM));
+    let mut entries = Vec::with_capacity(batch.len());
+    for (path, attrs) in batch {
+        let path = to_binary(env, path.as_os_str().as_bytes()).map_err(nomem)?;
+        entries.push((path, to_map(env, attrs, parse).map_err(nomem)?));
+    }
+    Ok(entries.encode(env))
+}
+
+// The root is visited right away so that errors about it are returned directly, everything
+// below it is left to the workers
+#[allow(clippy::too_many_arguments)]
+#[rustler::nif(schedule = "DirtyIo")]
+fn start_tree_scan<'a>(
+    env: Env<'a>,
+    root: Binary,
+    tag: Term<'a>,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+    threads: usize,
+    batch_size: usize,
+) -> NifResult<ResourceArc<TreeScan>> {
+    let root = as_path(&root).to_owned();
+    let options = Options { max_depth, follow, cross_mounts };
+    let metadata = tree::metadata(&root, follow)?;
+
+    let shared = Arc::new(Shared {
+        options,
+        prefix: prefix.map_or_else(Vec::new, |prefix| prefix.as_slice().to_vec()),
+        strip,
+        batch_size: batch_size.max(1),
+        root_dev: metadata.dev(),
+        queue: Mutex::new(Queue { dirs: Vec::new(), busy: 0 }),
+        queue_changed: Condvar::new(),
+        visited: Mutex::new(HashSet::new()),
+        credits: Mutex::new(WINDOW),
+        credits_changed: Condvar::new(),
+        cancelled: AtomicBool::new(false),
+    });
+
+    let (tx, rx) = mpsc::sync_channel(threads.max(1));
+    if let Some(entries) = tree::dump_entry(&root, follow, shared.prefix())? {
+        // Can't block, the channel is empty and has room for at least one batch
+        let _ = tx.send(Ok(vec![(root.clone(), entries)]));
+    }
+    if shared.descends(&metadata, 0) {
+        shared.push_dir(root, 0);
+    }
+
+    let tag_env = OwnedEnv::new();
+    let tag = tag_env.save(tag);
+    let outbox = Outbox { env: OwnedEnv::new(), pid: env.pid(), tag_env, tag };
+    let sender_shared = shared.clone();
+    thread::Builder::new()
+        .name("ex_attr_scan".into())
+        .spawn(move || deliver(sender_shared, rx, outbox, parse))
+        .map_err(|e| XattrError::new(e, "pthread_create"))?;
+
+    for _ in 0..threads.max(1) {
+        let (worker_shared, tx) = (shared.clone(), tx.clone());
+        let spawned = thread::Builder::new()
+            .name("ex_attr_scan".into())
+            .spawn(move || worker(worker_shared, tx));
+        if let Err(e) = spawned {
+            shared.cancel();
+            return Err(XattrError::new(e, "pthread_create").into());
+        }
+    }
+
+    Ok(ResourceArc::new(TreeScan { shared }))
+}
+
+#[rustler::nif]
+fn ack_tree_scan(scan: ResourceArc<TreeScan>) -> Atom {
+    scan.shared.give_credit();
+    atom::ok()
+}
@@ -0,0 +1,224 @@
+//! Snapshots of the attributes of a tree, serialized as JSON or CBOR.
+//!
+//! Both formats share one schema, a version and the list of files with attributes:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "files": [
+//!     {"path": "data/a.txt", "xattrs": [{"name": "user.foo", "value": "YmFy"}]}
+//!   ]
+//! }
+//! ```
+//!
+//! In JSON values are base64, and a path or name that isn't valid UTF-8 is written as a
+//! base64 `path_base64` or `name_base64` instead. In CBOR paths, names and values are all
+//! byte strings.
+
+use std::ffi::OsString;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::path::PathBuf;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use ciborium::Value as Cbor;
+use rustler::types::atom;
+use rustler::{Atom, Binary, Env, NifResult};
+use serde_json::{Map, Value as Json};
+
+use crate::dump::Entries;
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::tree::{self, Options, Walker};
+use crate::{as_path, to_binary, validate};
+
+const VERSION: u64 = 1;
+
+#[derive(Clone, Copy, rustler::NifUnitEnum)]
+pub enum Format {
+    Json,
+    Cbor,
+}
+
+pub type Files = Vec<(PathBuf, Entries)>;
+
+fn to_json(files: Files) -> Vec<u8> {
+    // Text when possible, base64 under a suffixed key otherwise
+    let put = |object: &mut Map<String, Json>, key: &str, bytes: &[u8]| {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => object.insert(key.to_owned(), text.into()),
+            Err(_) => object.insert(format!("{key}_base64"), BASE64.encode(bytes).into()),
+        };
+    };
+
+    let mut entries = Vec::with_capacity(files.len());
+    for (path, attrs) in files {
+        let mut file = Map::new();
+        put(&mut file, "path", path.as_os_str().as_bytes());
+        let xattrs = attrs.into_iter().map(|(name, value)| {
+            let mut xattr = Map::new();
+            put(&mut xattr, "name", name.as_bytes());
+            xattr.insert("value".to_owned(), BASE64.encode(value).into());
+            Json::Object(xattr)
+        });
+        file.insert("xattrs".to_owned(), xattrs.collect());
+        entries.push(Json::Object(file));
+    }
+
+    let snapshot = serde_json::json!({"version": VERSION, "files": entries});
+    snapshot.to_string().into_bytes()
+}
+
+fn from_json(snapshot: &[u8]) -> Result<Files, XattrError> {
+    let snapshot: Json =
+        serde_json::from_slice(snapshot).map_err(|_| XattrError::invalid_snapshot("malformed"))?;
+    check_version(snapshot.get("version").and_then(Json::as_u64))?;
+
+    // Either the text under `key` or the decoded base64 under its suffixed key
+    let get = |object: &Json, key: &str| -> Option<Vec<u8>> {
+        match object.get(key) {
+            Some(text) => Some(text.as_str()?.as_bytes().to_vec()),
+            None => BASE64.decode(object.get(format!("{key}_base64"))?.as_str()?).ok(),
+        }
+    };
+
+    let mut files = Vec::new();
+    for file in snapshot.get("files").and_then(Json::as_array).ok_or_else(schema)? {
+        let path = get(file, "path").ok_or_else(schema)?;
+        let mut attrs = Vec::new();
+        for xattr in file.get("xattrs").and_then(Json::as_array).ok_or_else(schema)? {
+            let name = get(xattr, "name").ok_or_else(schema)?;
+            let value = xattr.get("value").and_then(Json::as_str).ok_or_else(schema)?;
+            let value = BASE64.decode(value).map_err(|_| schema())?;
+            attrs.push((OsString::from_vec(name), value));
+        }
+        files.push((PathBuf::from(OsString::from_vec(path)), attrs));
+    }
+    Ok(files)
+}
+
+fn to_cbor(files: Files) -> Vec<u8> {
+    let text = |text: &str| Cbor::Text(text.to_owned());
+    let entries = files.into_iter().map(|(path, attrs)| {
+        let xattrs = attrs.into_iter().map(|(name, value)| {
+            Cbor::Map(vec![
+                (text("name"), Cbor::Bytes(name.into_vec())),
+                (text("value"), Cbor::Bytes(value)),
+            ])
+        });
+        Cbor::Map(vec![
+            (text("path"), Cbor::Bytes(path.into_os_string().into_vec())),
+            (text("xattrs"), Cbor::Array(xattrs.collect())),
+        ])
+    });
+
+    let snapshot = Cbor::Map(vec![
+        (text("version"), Cbor::Integer(VERSION.into())),
+        (text("files"), Cbor::Array(entries.collect())),
+    ]);
+    let mut out = Vec::new();
+    // Writing into memory can't fail
+    ciborium::into_writer(&snapshot, &mut out).unwrap();
+    out
+}
+
+fn from_cbor(snapshot: &[u8]) -> Result<Files, XattrError> {
+    let snapshot: Cbor =
+        ciborium::from_reader(snapshot).map_err(|_| XattrError::invalid_snapshot("malformed"))?;
+
+    fn get<'v>(map: &'v Cbor, key: &str) -> Option<&'v Cbor> {
+        let map = map.as_map()?;
+        map.iter().find(|(k, _)| k.as_text() == Some(key)).map(|(_, value)| value)
+    }
+    let bytes = |map: &Cbor, key: &str| Some(get(map, key)?.as_bytes()?.clone());
+    let version = get(&snapshot, "version").and_then(|version| version.as_integer());
+    check_version(version.and_then(|version| u64::try_from(version).ok()))?;
+
+    let mut files = Vec::new();
+    for file in get(&snapshot, "files").and_then(Cbor::as_array).ok_or_else(schema)? {
+        let path = bytes(file, "path").ok_or_else(schema)?;
+        let mut attrs = Vec::new();
+        for xattr in get(file, "xattrs").and_then(Cbor::as_array).ok_or_else(schema)? {
+            let name = bytes(xattr, "name").ok_or_else(schema)?;
+            let value = bytes(xattr, "value").ok_or_else(schema)?;
+            attrs.push((OsString::from_vec(name), value));
+        }
+        files.push((PathBuf::from(OsString::from_vec(path)), attrs));
+    }
+    Ok(files)
+}
+
+fn schema() -> XattrError {
+    XattrError::invalid_snapshot("schema")
+}
+
+fn check_version(version: Option<u64>) -> Result<(), XattrError> {
+    match version {
+        Some(VERSION) => Ok(()),
+        Some(_) => Err(XattrError::invalid_snapshot("version")),
+        None => Err(schema()),
+    }
+}
+
+// Sets every attribute listed for every file, after checking all of them so that bad
+// input changes nothing. Attributes the files have but aren't listed are kept.
+pub fn apply(files: &[(PathBuf, Entries)], follow: bool) -> Result<(), XattrError> {
+    for (path, entries) in files {
+        for (name, value) in entries {
+            validate::name(name)
+                .and_then(|_| validate::value(name, value))
+                .map_err(|e| e.with_path(path))?;
+        }
+    }
+
+    let syscall = if follow { "setxattr" } else { "lsetxattr" };
+    for (path, entries) in files {
+        for (name, value) in entries {
+            let result = if follow {
+                xattr::set_deref(path, name, value)
+            } else {
+                xattr::set(path, name, value)
+            };
+            result.map_err(|e| XattrError::new(e, syscall).with_name(name).with_path(path))?;
+        }
+    }
+    Ok(())
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn snapshot_xattr<'a>(
+    env: Env<'a>,
+    root: Binary,
+    format: Format,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+) -> NifResult<Binary<'a>> {
+    let options = Options { max_depth, follow, cross_mounts };
+    let prefix = Prefix::new(prefix.as_ref(), false);
+
+    let mut files = Vec::new();
+    for path in Walker::new(as_path(&root).to_owned(), options) {
+        let path = path?;
+        if let Some(entries) = tree::dump_entry(&path, follow, prefix)? {
+            files.push((path, entries));
+        }
+    }
+    let snapshot = match format {
+        Format::Json => to_json(files),
+        Format::Cbor => to_cbor(files),
+    };
+    to_binary(env, &snapshot)
+}
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn restore_xattr(snapshot: Binary, format: Format, follow: bool) -> NifResult<Atom> {
+    let files = match format {
+        Format::Json => from_json(snapshot.as_slice())?,
+        Format::Cbor => from_cbor(snapshot.as_slice())?,
+    };
+    apply(&files, follow)?;
+    Ok(atom::ok())
+}
+
@@ -0,0 +1,103 @@
+//! Tree walks consumed a batch at a time.
+//!
+//! The walker lives in a resource and only advances when the next batch is asked for, so
+//! nothing beyond the current batch is ever held in memory. Closing the stream drops the
+//! walker right away instead of waiting for the resource to be garbage collected, or as
+//! soon as the batch being walked is done if there is one.
+
+use std::os::unix::ffi::OsStrExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use rustler::types::atom;
+use rustler::{Atom, Binary, Env, NifResult, ResourceArc, Term};
+
+use crate::prefix::Prefix;
+use crate::tree::{self, Options, Walker};
+use crate::{as_path, to_binary, to_map};
+
+struct Cursor {
+    walker: Walker,
+    prefix: Vec<u8>,
+    strip: bool,
+    parse: bool,
+}
+
+pub struct TreeStream {
+    // `None` once closed and no batch is being walked
+    cursor: Mutex<Option<Cursor>>,
+    // Closing doesn't wait for the cursor, which is held for as long as a batch is walked
+    closed: AtomicBool,
+}
+
+impl TreeStream {
+    fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+}
+
+// Nothing is touched until the first batch is asked for, so errors about the root are
+// returned from there
+#[allow(clippy::too_many_arguments)]
+#[rustler::nif]
+fn open_tree_stream(
+    root: Binary,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> ResourceArc<TreeStream> {
+    let options = Options { max_depth, follow, cross_mounts };
+    let cursor = Cursor {
+        walker: Walker::new(as_path(&root).to_owned(), options),
+        prefix: prefix.map_or_else(Vec::new, |prefix| prefix.as_slice().to_vec()),
+        strip,
+        parse,
+    };
+    ResourceArc::new(TreeStream {
+        cursor: Mutex::new(Some(cursor)),
+        closed: AtomicBool::new(false),
+    })
+}
+
+// Walks until `size` files with attributes are found, an empty batch means the walk is over
+#[rustler::nif(schedule = "DirtyIo")]
+fn next_tree_stream<'a>(
+    env: Env<'a>,
+    stream: ResourceArc<TreeStream>,
+    size: usize,
+) -> NifResult<Vec<(Binary<'a>, Term<'a>)>> {
+    let mut guard = stream.cursor.lock().unwrap();
+    let Some(cursor) = guard.as_mut().filter(|_| !stream.is_closed()) else {
+        guard.take();
+        return Ok(Vec::new());
+    };
+    let follow = cursor.walker.follows();
+    let prefix = Prefix::from_bytes(&cursor.prefix, cursor.strip);
+
+    let mut batch = Vec::new();
+    while batch.len() < size.max(1) && !stream.is_closed() {
+        let Some(path) = cursor.walker.next() else { break };
+        let path = path?;
+        if let Some(entries) = tree::dump_entry(&path, follow, prefix)? {
+            let attrs = to_map(env, entries, cursor.parse)?;
+            batch.push((to_binary(env, path.as_os_str().as_bytes())?, attrs));
+        }
+    }
+    if stream.is_closed() {
+        guard.take();
+    }
+    Ok(batch)
+}
+
+#[rustler::nif]
+fn close_tree_stream(stream: ResourceArc<TreeStream>) -> Atom {
+    stream.closed.store(true, Ordering::Relaxed);
+    // Left to `next_tree_stream` to drop when it's busy walking
+    if let Ok(mut cursor) = stream.cursor.try_lock() {
+        cursor.take();
+    }
+    atom::ok()
+}
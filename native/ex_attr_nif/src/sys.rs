@@ -0,0 +1,101 @@
+//! Extended attribute syscalls that the `xattr` crate doesn't expose, called directly
+//! through rustix on the platforms it implements them for (Linux, Android and macOS).
+
+use std::ffi::OsStr;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use rustix::fs::{Mode, OFlags};
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+use rustix::fs as rfs;
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+use std::os::unix::io::AsFd;
+#[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+use xattr::FileExt;
+
+use crate::SetMode;
+
+// Opens a file only so its attributes can be managed through the descriptor. This has to
+// be a real read-only open rather than `O_PATH`, Linux refuses the f*xattr calls on those.
+// Non-blocking so FIFOs don't hang.
+pub fn open(path: &Path, follow: bool) -> io::Result<File> {
+    let mut flags = OFlags::RDONLY | OFlags::NONBLOCK | OFlags::NOCTTY | OFlags::CLOEXEC;
+    if !follow {
+        flags |= OFlags::NOFOLLOW;
+    }
+    Ok(rustix::fs::open(path, flags, Mode::empty())?.into())
+}
+
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+pub fn set(
+    path: &Path,
+    name: &OsStr,
+    value: &[u8],
+    follow: bool,
+    mode: SetMode,
+) -> io::Result<()> {
+    let setxattr_func = if follow { rfs::setxattr } else { rfs::lsetxattr };
+    setxattr_func(path, name, value, flags(mode))?;
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+pub fn set(
+    _path: &Path,
+    _name: &OsStr,
+    _value: &[u8],
+    _follow: bool,
+    _mode: SetMode,
+) -> io::Result<()> {
+    Err(io::ErrorKind::Unsupported.into())
+}
+
+// Size of an attribute's value without reading it, by passing getxattr(2) an empty buffer.
+// Returns `None` if the attribute doesn't exist.
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+pub fn size(path: &Path, name: &OsStr, follow: bool) -> io::Result<Option<usize>> {
+    let getxattr_func = if follow { rfs::getxattr } else { rfs::lgetxattr };
+    match getxattr_func(path, name, &mut []) {
+        Ok(size) => Ok(Some(size)),
+        Err(ENOATTR) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The BSDs have no rustix support, fallback to reading the value
+#[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+pub fn size(path: &Path, name: &OsStr, follow: bool) -> io::Result<Option<usize>> {
+    let value = if follow { xattr::get_deref(path, name) } else { xattr::get(path, name) };
+    Ok(value?.map(|value| value.len()))
+}
+
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+pub fn fset(file: &File, name: &OsStr, value: &[u8], mode: SetMode) -> io::Result<()> {
+    rfs::fsetxattr(file.as_fd(), name, value, flags(mode))?;
+    Ok(())
+}
+
+// Only plain upserts are possible through the `xattr` crate
+#[cfg(not(any(target_os = "android", target_os = "linux", target_os = "macos")))]
+pub fn fset(file: &File, name: &OsStr, value: &[u8], mode: SetMode) -> io::Result<()> {
+    match mode {
+        SetMode::Upsert => file.set_xattr(name, value),
+        _ => Err(io::ErrorKind::Unsupported.into()),
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux", target_os = "macos"))]
+fn flags(mode: SetMode) -> rfs::XattrFlags {
+    match mode {
+        SetMode::Create => rfs::XattrFlags::CREATE,
+        SetMode::Replace => rfs::XattrFlags::REPLACE,
+        SetMode::Upsert => rfs::XattrFlags::empty(),
+    }
+}
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+const ENOATTR: rustix::io::Errno = rustix::io::Errno::NODATA;
+
+#[cfg(target_os = "macos")]
+const ENOATTR: rustix::io::Errno = rustix::io::Errno::NOATTR;
@@ -0,0 +1,138 @@
+//! Temporary files that only show up at their final path once they are complete.
+//!
+//! The temporary file is created next to its destination, so that renaming it into place
+//! never crosses a filesystem and readers either see the old file or the new one. It is
+//! removed again if it is dropped before being persisted.
+//!
+//! On Linux the file can also be created with `O_TMPFILE`, which gives it no name at all
+//! until it is persisted, so not even a crash can leave it behind half written. Naming it
+//! takes `/proc`, without it a named temporary file is used instead.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::error::XattrError;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+pub struct TempFile {
+    pub file: File,
+    // `None` while an `O_TMPFILE` file has no name yet
+    path: Option<PathBuf>,
+    persisted: bool,
+}
+
+fn parent(dst: &Path) -> &Path {
+    match dst.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    }
+}
+
+// Calls `create` with names `.<name>.<pid>.<n>.tmp` next to `dst` until one is free. They
+// are named after the destination so leftovers of a crash are easy to trace back.
+fn with_free_name<T>(
+    dst: &Path,
+    mut create: impl FnMut(&Path) -> io::Result<T>,
+) -> io::Result<(PathBuf, T)> {
+    let name = dst.file_name().unwrap_or_default();
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let mut temp = OsString::from(".");
+        temp.push(name);
+        temp.push(format!(".{}.{count}.tmp", process::id()));
+        let path = parent(dst).join(temp);
+        match create(&path) {
+            Ok(created) => return Ok((path, created)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+impl TempFile {
+    // `mode` is subject to the umask, the same as for any new file
+    pub fn new(dst: &Path, mode: u32) -> Result<Self, XattrError> {
+        let open = |path: &Path| {
+            OpenOptions::new().write(true).create_new(true).mode(mode).open(path)
+        };
+        let (path, file) =
+            with_free_name(dst, open).map_err(|e| XattrError::new(e, "open").with_path(dst))?;
+        Ok(TempFile { file, path: Some(path), persisted: false })
+    }
+
+    // An `O_TMPFILE` file where the filesystem supports them and it can be linked through
+    // `/proc` later (which containers and chroots don't always mount), a named one otherwise
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    pub fn anonymous(dst: &Path, mode: u32) -> Result<Self, XattrError> {
+        use rustix::fs::{Mode, OFlags};
+        use rustix::io::Errno;
+
+        if !Path::new("/proc/self/fd").is_dir() {
+            return TempFile::new(dst, mode);
+        }
+        let flags = OFlags::TMPFILE | OFlags::WRONLY | OFlags::CLOEXEC;
+        match rustix::fs::open(parent(dst), flags, Mode::from_raw_mode(mode)) {
+            Ok(fd) => Ok(TempFile { file: fd.into(), path: None, persisted: false }),
+            // What kernels and filesystems without `O_TMPFILE` support fail with
+            Err(Errno::OPNOTSUPP | Errno::ISDIR | Errno::INVAL) => TempFile::new(dst, mode),
+            Err(e) => Err(XattrError::new(e.into(), "open").with_path(dst)),
+        }
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    pub fn anonymous(dst: &Path, mode: u32) -> Result<Self, XattrError> {
+        TempFile::new(dst, mode)
+    }
+
+    // Gives an `O_TMPFILE` file a temporary name through its `/proc` link, since `rename`
+    // can replace an existing destination but `linkat` can't
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn link(&self, dst: &Path) -> io::Result<PathBuf> {
+        use rustix::fs::{AtFlags, CWD};
+        use std::os::unix::io::AsRawFd;
+
+        let proc = format!("/proc/self/fd/{}", self.file.as_raw_fd());
+        let link = |path: &Path| {
+            rustix::fs::linkat(CWD, proc.as_str(), CWD, path, AtFlags::SYMLINK_FOLLOW)
+                .map_err(io::Error::from)
+        };
+        with_free_name(dst, link).map(|(path, _)| path)
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    fn link(&self, _dst: &Path) -> io::Result<PathBuf> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    // Flushes the data to disk and atomically replaces `dst` with the file, then flushes
+    // the directory so the rename itself survives a crash. That last flush is only best
+    // effort, failing it doesn't undo the rename so it isn't reported as a failed write.
+    pub fn persist(mut self, dst: &Path) -> Result<(), XattrError> {
+        self.file.sync_all().map_err(|e| XattrError::new(e, "fsync").with_path(dst))?;
+        if self.path.is_none() {
+            let link = self.link(dst).map_err(|e| XattrError::new(e, "linkat").with_path(dst))?;
+            self.path = Some(link);
+        }
+        if let Some(path) = &self.path {
+            fs::rename(path, dst).map_err(|e| XattrError::new(e, "rename").with_path(dst))?;
+        }
+        self.persisted = true;
+
+        let _ = File::open(parent(dst)).and_then(|dir| dir.sync_all());
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let (false, Some(path)) = (self.persisted, &self.path) {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
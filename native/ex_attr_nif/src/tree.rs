@@ -0,0 +1,163 @@
+//! Recursive directory walks.
+//!
+//! The walk is depth first with the entries of each directory visited in name order, so
+//! results are stable between runs. Files that vanish mid-walk are skipped, any other
+//! error stops the walk and carries the path it happened on.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::fs::{self, Metadata};
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+
+use rustler::{Binary, Env, NifResult, Term};
+
+use crate::error::XattrError;
+use crate::prefix::Prefix;
+use crate::{as_path, dump, to_binary, to_map};
+
+#[derive(Clone, Copy)]
+pub struct Options {
+    // Depth of the deepest entries visited, the root being at depth 0
+    pub max_depth: Option<usize>,
+    pub follow: bool,
+    // When false, mount points are still visited but not descended into
+    pub cross_mounts: bool,
+}
+
+impl Options {
+    // Whether the directory at `depth` should be descended into, cycles aside
+    pub fn descends(&self, metadata: &Metadata, depth: usize, root_dev: u64) -> bool {
+        metadata.is_dir()
+            && self.max_depth.is_none_or(|max_depth| depth < max_depth)
+            && (self.cross_mounts || metadata.dev() == root_dev)
+    }
+}
+
+pub fn metadata(path: &Path, follow: bool) -> Result<Metadata, XattrError> {
+    if follow {
+        fs::metadata(path).map_err(|e| XattrError::new(e, "stat"))
+    } else {
+        fs::symlink_metadata(path).map_err(|e| XattrError::new(e, "lstat"))
+    }
+}
+
+// Names of the entries in a directory, none if it vanished
+pub fn read_dir(path: &Path) -> Result<Vec<OsString>, XattrError> {
+    let entries = match fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(XattrError::new(e, "opendir")),
+    };
+    entries
+        .map(|entry| entry.map(|entry| entry.file_name()))
+        .collect::<io::Result<Vec<_>>>()
+        .map_err(|e| XattrError::new(e, "readdir"))
+}
+
+pub struct Walker {
+    options: Options,
+    // Paths left to visit along with their depth, popped from the end
+    pending: Vec<(PathBuf, usize)>,
+    root_dev: Option<u64>,
+    // Directories already descended into, only tracked when following symlinks since
+    // that is the only way to run into a cycle
+    visited: HashSet<(u64, u64)>,
+}
+
+impl Walker {
+    pub fn new(root: PathBuf, options: Options) -> Self {
+        Walker { options, pending: vec![(root, 0)], root_dev: None, visited: HashSet::new() }
+    }
+
+    pub fn follows(&self) -> bool {
+        self.options.follow
+    }
+
+    // Whether `path` still exists, queueing its children if it's a directory to descend
+    fn visit(&mut self, path: &Path, depth: usize) -> Result<bool, XattrError> {
+        let metadata = match metadata(path, self.options.follow) {
+            Ok(metadata) => metadata,
+            Err(e) if depth > 0 && e.is_vanished() => return Ok(false),
+            Err(e) => return Err(e),
+        };
+
+        let root_dev = *self.root_dev.get_or_insert(metadata.dev());
+        let descend = self.options.descends(&metadata, depth, root_dev)
+            && (!self.options.follow || self.visited.insert((metadata.dev(), metadata.ino())));
+
+        if descend {
+            self.queue_children(path, depth + 1)?;
+        }
+        Ok(true)
+    }
+
+    fn queue_children(&mut self, path: &Path, depth: usize) -> Result<(), XattrError> {
+        let mut names = read_dir(path)?;
+        // Reversed so the first name ends up on top of the stack
+        names.sort_unstable_by(|a, b| b.as_bytes().cmp(a.as_bytes()));
+        self.pending.extend(names.into_iter().map(|name| (path.join(name), depth)));
+        Ok(())
+    }
+}
+
+impl Iterator for Walker {
+    type Item = Result<PathBuf, XattrError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some((path, depth)) = self.pending.pop() {
+            match self.visit(&path, depth) {
+                Ok(true) => return Some(Ok(path)),
+                Ok(false) => continue,
+                Err(e) => {
+                    self.pending.clear();
+                    return Some(Err(e.with_path(&path)));
+                }
+            }
+        }
+        None
+    }
+}
+
+// The attributes of one walked file, `None` if it has none matching the prefix or vanished
+// since being walked
+pub fn dump_entry(
+    path: &Path,
+    follow: bool,
+    prefix: Prefix,
+) -> Result<Option<dump::Entries>, XattrError> {
+    match dump::dump(path, follow, prefix) {
+        Ok(entries) if entries.is_empty() => Ok(None),
+        Ok(entries) => Ok(Some(entries)),
+        Err(e) if e.is_vanished() => Ok(None),
+        Err(e) => Err(e.with_path(path)),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+#[rustler::nif(schedule = "DirtyIo")]
+fn dump_tree_xattr<'a>(
+    env: Env<'a>,
+    root: Binary,
+    follow: bool,
+    max_depth: Option<usize>,
+    cross_mounts: bool,
+    prefix: Option<Binary>,
+    strip: bool,
+    parse: bool,
+) -> NifResult<Vec<(Binary<'a>, Term<'a>)>> {
+    let options = Options { max_depth, follow, cross_mounts };
+    let prefix = Prefix::new(prefix.as_ref(), strip);
+
+    let mut tree = Vec::new();
+    for path in Walker::new(as_path(&root).to_owned(), options) {
+        let path = path?;
+        if let Some(entries) = dump_entry(&path, follow, prefix)? {
+            let attrs = to_map(env, entries, parse)?;
+            tree.push((to_binary(env, path.as_os_str().as_bytes())?, attrs));
+        }
+    }
+    Ok(tree)
+}
@@ -0,0 +1,60 @@
+//! Checks attribute names and values before they reach the kernel.
+//!
+//! The kernel reports all of these as an ambiguous `EINVAL`, `ERANGE` or `E2BIG`, catching
+//! them up front lets us say exactly what is wrong with the input instead.
+
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
+
+use crate::error::XattrError;
+#[cfg(not(target_os = "macos"))]
+use crate::name::Namespace;
+
+// Limits from linux/limits.h
+const XATTR_NAME_MAX: usize = 255;
+const XATTR_SIZE_MAX: usize = 65536;
+
+pub fn name(name: &OsStr) -> Result<(), XattrError> {
+    check_name(name.as_bytes()).map_err(|kind| XattrError::invalid_name(kind).with_name(name))
+}
+
+fn check_name(name: &[u8]) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("empty");
+    }
+    if name.len() > XATTR_NAME_MAX {
+        return Err("too_long");
+    }
+    if name.contains(&0) {
+        return Err("nul_byte");
+    }
+    check_namespace(name)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn check_namespace(name: &[u8]) -> Result<(), &'static str> {
+    let Some(dot) = name.iter().position(|&b| b == b'.') else {
+        return Err("no_namespace");
+    };
+    if Namespace::from_bytes(&name[..dot]).is_none() {
+        return Err("unknown_namespace");
+    }
+    if dot + 1 == name.len() {
+        return Err("empty_key");
+    }
+    Ok(())
+}
+
+// macOS has no namespaces, names there are free-form (ex: `com.apple.quarantine`)
+#[cfg(target_os = "macos")]
+fn check_namespace(_name: &[u8]) -> Result<(), &'static str> {
+    Ok(())
+}
+
+// Only Linux caps values, other platforms allow much bigger ones (ex: macOS resource forks)
+pub fn value(name: &OsStr, value: &[u8]) -> Result<(), XattrError> {
+    if cfg!(any(target_os = "android", target_os = "linux")) && value.len() > XATTR_SIZE_MAX {
+        return Err(XattrError::invalid_value("too_big").with_name(name));
+    }
+    Ok(())
+}
@@ -0,0 +1,47 @@
+//! Writing a file and its attributes in one atomic step.
+//!
+//! The contents and attributes are written to an `O_TMPFILE` file (or a temporary name
+//! where those aren't supported) in the destination's directory, which is synced and only
+//! then linked and renamed over the destination. Readers either see the previous file or
+//! the new one complete with its attributes, never a file without them.
+
+use std::fs;
+use std::io::Write;
+
+use rustler::types::atom;
+use rustler::{Atom, Binary, NifResult, Term};
+
+use crate::decode;
+use crate::error::XattrError;
+use crate::temp::TempFile;
+use crate::{as_path, sys, validate, SetMode};
+
+#[rustler::nif(schedule = "DirtyIo")]
+fn write_xattr(path: Binary, contents: Binary, attrs: Term) -> NifResult<Atom> {
+    let path = as_path(&path);
+    // Names are checked while decoding, values before anything is created
+    let attrs = decode::attrs(attrs)?;
+    for (name, value) in &attrs {
+        validate::value(name, value)?;
+    }
+
+    // The file being replaced keeps its permissions, a new one gets the usual defaults
+    let existing = fs::symlink_metadata(path).ok().filter(|metadata| metadata.is_file());
+
+    let mut temp = TempFile::anonymous(path, 0o666)?;
+    temp.file
+        .write_all(contents.as_slice())
+        .map_err(|e| XattrError::new(e, "write").with_path(path))?;
+    for (name, value) in &attrs {
+        sys::fset(&temp.file, name, value, SetMode::Upsert)
+            .map_err(|e| XattrError::new(e, "fsetxattr").with_name(name).with_path(path))?;
+    }
+    // Only now, a read-only mode would keep the attributes above from being set
+    if let Some(metadata) = existing {
+        temp.file
+            .set_permissions(metadata.permissions())
+            .map_err(|e| XattrError::new(e, "fchmod").with_path(path))?;
+    }
+    temp.persist(path)?;
+    Ok(atom::ok())
+}
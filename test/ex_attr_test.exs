@@ -2,7 +2,896 @@ defmodule ExAttrTest do
   use ExUnit.Case
   doctest ExAttr
 
+  @moduletag :tmp_dir
+
+  setup %{tmp_dir: tmp_dir} do
+    path = Path.join(tmp_dir, "test.txt")
+    File.touch!(path)
+    %{path: path}
+  end
+
   test "greets the world" do
     assert ExAttr.hello() == :world
   end
+
+  describe "get/2" do
+    test "returns values as binaries with their exact bytes", %{path: path} do
+      value = <<0, 255, 128, "abc", 0>>
+      :ok = ExAttr.set(path, "user.bin", value)
+      assert {:ok, ^value} = ExAttr.get(path, "user.bin")
+    end
+
+    test "handles large values", %{path: path} do
+      value = :binary.copy("x", 4000)
+      :ok = ExAttr.set(path, "user.large", value)
+      assert {:ok, ^value} = ExAttr.get(path, "user.large")
+    end
+  end
+
+  describe "raw names" do
+    test "lists attribute names that are not valid UTF-8", %{path: path} do
+      name = <<"user.", 255, 254>>
+      :ok = ExAttr.set(path, name, "value")
+      assert {:ok, names} = ExAttr.list(path)
+      assert name in names
+      assert {:ok, "value"} = ExAttr.get(path, name)
+    end
+
+    test "reaches files whose path is not valid UTF-8", %{tmp_dir: tmp_dir} do
+      path = Path.join(tmp_dir, <<"file-", 255>>)
+      File.touch!(path)
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      assert {:ok, "bar"} = ExAttr.get(path, "user.foo")
+    end
+  end
+
+  describe "list/2 prefixes" do
+    setup %{path: path} do
+      :ok = ExAttr.set(path, "user.app.foo", "1")
+      :ok = ExAttr.set(path, "user.app.bar", "2")
+      :ok = ExAttr.set(path, "user.other", "3")
+    end
+
+    test "only lists matching names", %{path: path} do
+      assert {:ok, names} = ExAttr.list(path, prefix: "user.app.")
+      assert Enum.sort(names) == ["user.app.bar", "user.app.foo"]
+    end
+
+    test "strips the prefix", %{path: path} do
+      assert {:ok, names} = ExAttr.list(path, prefix: "user.app.", strip_prefix: true)
+      assert Enum.sort(names) == ["bar", "foo"]
+    end
+
+    test "works on handles", %{path: path} do
+      handle = ExAttr.Handle.open!(path)
+      assert {:ok, names} = ExAttr.list(handle, prefix: "user.", strip_prefix: true)
+      assert Enum.sort(names) == ["app.bar", "app.foo", "other"]
+    end
+  end
+
+  defmodule Namespaced do
+    use ExAttr.Namespace, prefix: "user.myapp"
+  end
+
+  describe "ExAttr.Namespace" do
+    test "prefixes names", %{path: path} do
+      assert "user.myapp." = Namespaced.namespace()
+      :ok = Namespaced.set(path, "status", "pending")
+      assert {:ok, "pending"} = ExAttr.get(path, "user.myapp.status")
+      assert {:ok, "pending"} = Namespaced.get(path, "status")
+      :ok = Namespaced.remove(path, "status")
+      assert {:ok, nil} = ExAttr.get(path, "user.myapp.status")
+    end
+
+    test "lists and dumps only its own attributes", %{path: path} do
+      :ok = ExAttr.set(path, "user.other", "1")
+      :ok = ExAttr.set(path, "user.myappx", "2")
+      :ok = Namespaced.set(path, "status", "pending")
+      assert {:ok, ["status"]} = Namespaced.list(path)
+      assert {:ok, %{"status" => "pending"}} = Namespaced.dump(path)
+      assert %{"status" => "pending"} = Namespaced.dump!(ExAttr.Handle.open!(path))
+    end
+  end
+
+  describe "ExAttr.Name" do
+    test "parses and formats names" do
+      assert {:ok, %ExAttr.Name{namespace: :user, key: "a.b"} = name} = ExAttr.Name.parse("user.a.b")
+      assert {:ok, "user.a.b"} = ExAttr.Name.format(name)
+      assert "trusted.foo" = to_string(%ExAttr.Name{namespace: :trusted, key: "foo"})
+      assert {:error, {:invalid_name, :unknown_namespace}} = ExAttr.Name.parse("usr.foo")
+      assert {:error, {:invalid_name, :empty_key}} = ExAttr.Name.format(%ExAttr.Name{namespace: :user, key: ""})
+    end
+
+    test "list/2 and dump/2 return structured names", %{path: path} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      name = %ExAttr.Name{namespace: :user, key: "foo"}
+      assert {:ok, [^name]} = ExAttr.list(path, parse_names: true)
+      assert {:ok, %{^name => "bar"}} = ExAttr.dump(ExAttr.Handle.open!(path), parse_names: true)
+      assert_raise ArgumentError, fn -> ExAttr.list(path, parse_names: true, strip_prefix: true) end
+    end
+  end
+
+  describe "dump/2" do
+    test "returns every attribute", %{path: path} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      :ok = ExAttr.set(path, "user.bin", <<0, 255>>)
+      assert {:ok, %{"user.foo" => "bar", "user.bin" => <<0, 255>>}} = ExAttr.dump(path)
+    end
+
+    test "works on files that can't be opened", %{path: path} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      File.chmod!(path, 0o000)
+      assert {:ok, %{"user.foo" => "bar"}} = ExAttr.dump(path)
+    end
+
+    test "returns posix errors", %{tmp_dir: tmp_dir} do
+      assert {:error, :enoent} = ExAttr.dump(Path.join(tmp_dir, "missing"))
+    end
+  end
+
+  describe "dump_tree/2" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      File.mkdir_p!(Path.join(tmp_dir, "a/b"))
+      deep = Path.join(tmp_dir, "a/b/deep.txt")
+      File.touch!(deep)
+      :ok = ExAttr.set(path, "user.foo", "1")
+      :ok = ExAttr.set(deep, "user.foo", "2")
+      :ok = ExAttr.set(deep, "user.bar", "3")
+      %{deep: deep}
+    end
+
+    test "returns every file with attributes in order", %{tmp_dir: tmp_dir, path: path, deep: deep} do
+      assert {:ok, [{^deep, %{"user.foo" => "2", "user.bar" => "3"}}, {^path, %{"user.foo" => "1"}}]} =
+               ExAttr.dump_tree(tmp_dir)
+    end
+
+    test "honours max depth and prefixes", %{tmp_dir: tmp_dir, path: path, deep: deep} do
+      assert {:ok, [{^path, _}]} = ExAttr.dump_tree(tmp_dir, max_depth: 1)
+      assert {:ok, [{^deep, attrs}]} = ExAttr.dump_tree(tmp_dir, prefix: "user.bar")
+      assert attrs == %{"user.bar" => "3"}
+    end
+
+    test "only descends symlinked directories when following", %{tmp_dir: tmp_dir, deep: deep} do
+      root = Path.join(tmp_dir, "a")
+      other = Path.join(tmp_dir, "other")
+      File.mkdir_p!(other)
+      File.touch!(Path.join(other, "x.txt"))
+      :ok = ExAttr.set(Path.join(other, "x.txt"), "user.foo", "4")
+      :ok = File.ln_s(other, Path.join(root, "link"))
+      :ok = File.ln_s(root, Path.join(root, "b/loop"))
+
+      assert {:ok, [{^deep, _}]} = ExAttr.dump_tree(root)
+      assert {:ok, tree} = ExAttr.dump_tree(root, follow_symlinks: true)
+      assert Enum.map(tree, &elem(&1, 0)) == [deep, Path.join(root, "link/x.txt")]
+    end
+
+    test "fail with the root's error", %{tmp_dir: tmp_dir} do
+      assert {:error, :enoent} = ExAttr.dump_tree(Path.join(tmp_dir, "missing"))
+    end
+
+    test "spreads over worker threads", %{tmp_dir: tmp_dir} do
+      for i <- 1..50 do
+        file = Path.join(tmp_dir, "a/b/#{i}.txt")
+        File.touch!(file)
+        :ok = ExAttr.set(file, "user.n", "#{i}")
+      end
+
+      assert {:ok, sequential} = ExAttr.dump_tree(tmp_dir)
+      assert {:ok, parallel} = ExAttr.dump_tree(tmp_dir, threads: 4, batch_size: 3)
+      assert length(parallel) == 52
+      assert Enum.sort(parallel) == Enum.sort(sequential)
+      assert {:error, :enoent} = ExAttr.dump_tree(Path.join(tmp_dir, "missing"), threads: 2)
+    end
+
+    test "waits for batches to be acknowledged", %{tmp_dir: tmp_dir} do
+      for i <- 1..20, do: :ok = ExAttr.set(touch(tmp_dir, "#{i}.txt"), "user.n", "#{i}")
+      tag = make_ref()
+      scan = ExAttr.Nif.start_tree_scan(tmp_dir, tag, false, nil, false, nil, false, false, 4, 1)
+
+      # Only `WINDOW` (2) batches are sent until the first one is acknowledged
+      Process.sleep(100)
+      assert [_, _] = receive_batches(tag)
+      :ok = ExAttr.Nif.ack_tree_scan(scan)
+      assert_receive {^tag, {:batch, _}}
+      Process.sleep(100)
+      assert receive_batches(tag) == []
+    end
+
+    test "sends nothing after an error", %{tmp_dir: tmp_dir} do
+      for i <- 1..20, do: :ok = ExAttr.set(touch(tmp_dir, "#{i}.txt"), "user.n", "#{i}")
+
+      # Paths under `deep/rest` are longer than PATH_MAX, which only moving one half of the
+      # tree under the other can get to. Moved back so the directory can be cleaned up.
+      name = String.duplicate("d", 250)
+      deep = Path.join([tmp_dir, "deep" | List.duplicate(name, 8)])
+      File.mkdir_p!(deep)
+      File.mkdir_p!(Path.join([tmp_dir, "rest" | List.duplicate(name, 9)]))
+      :ok = File.rename(Path.join(tmp_dir, "rest"), Path.join(deep, "rest"))
+      on_exit(fn -> File.rename(Path.join(deep, "rest"), Path.join(tmp_dir, "rest")) end)
+
+      tag = make_ref()
+      scan = ExAttr.Nif.start_tree_scan(tmp_dir, tag, false, nil, false, nil, false, false, 4, 1)
+      assert {:error, %{reason: :enametoolong}} = receive_until_batches_end(scan, tag)
+      Process.sleep(100)
+      refute_received {^tag, _}
+    end
+
+    test "stops its threads once the caller exits", %{tmp_dir: tmp_dir} do
+      for i <- 1..20, do: :ok = ExAttr.set(touch(tmp_dir, "#{i}.txt"), "user.n", "#{i}")
+      parent = self()
+
+      # The caller never acknowledges anything, leaving the workers blocked on it
+      caller =
+        spawn(fn ->
+          _scan =
+            ExAttr.Nif.start_tree_scan(tmp_dir, :scan, false, nil, false, nil, false, false, 4, 1)
+
+          send(parent, :started)
+          Process.sleep(:infinity)
+        end)
+
+      assert_receive :started
+      assert scan_threads() > 0
+      Process.exit(caller, :kill)
+      assert eventually(fn -> scan_threads() == 0 end)
+    end
+  end
+
+  describe "stream_tree/2" do
+    setup %{tmp_dir: tmp_dir} do
+      for i <- 1..10 do
+        file = Path.join(tmp_dir, "#{i}.txt")
+        File.touch!(file)
+        :ok = ExAttr.set(file, "user.n", "#{i}")
+      end
+    end
+
+    test "yields the same entries as dump_tree/2", %{tmp_dir: tmp_dir} do
+      assert Enum.to_list(ExAttr.stream_tree(tmp_dir, batch_size: 3)) == ExAttr.dump_tree!(tmp_dir)
+    end
+
+    test "can be halted early", %{tmp_dir: tmp_dir} do
+      assert [{_, %{"user.n" => "1"}}, {_, %{"user.n" => "10"}}] =
+               ExAttr.stream_tree(tmp_dir, batch_size: 1) |> Enum.take(2)
+    end
+
+    test "stops walking once closed", %{tmp_dir: tmp_dir} do
+      stream = ExAttr.Nif.open_tree_stream(tmp_dir, false, nil, false, nil, false, false)
+      assert [{_, %{"user.n" => "1"}}] = ExAttr.Nif.next_tree_stream(stream, 1)
+      assert :ok = ExAttr.Nif.close_tree_stream(stream)
+      assert [] = ExAttr.Nif.next_tree_stream(stream, 1)
+      assert [] = ExAttr.Nif.next_tree_stream(stream, 100)
+    end
+
+    test "raises when run", %{tmp_dir: tmp_dir} do
+      stream = ExAttr.stream_tree(Path.join(tmp_dir, "missing"))
+      assert_raise ExAttr.Error, fn -> Enum.to_list(stream) end
+    end
+  end
+
+  describe "find/3" do
+    setup %{tmp_dir: tmp_dir} do
+      files =
+        for {name, attrs} <- [
+              a: %{"user.status" => "pending", "user.sum" => "abc"},
+              b: %{"user.status" => "done"},
+              c: %{"user.status" => "pending-retry", "user.sum" => String.duplicate("x", 64)}
+            ],
+            into: %{} do
+          file = Path.join(tmp_dir, "#{name}.txt")
+          File.touch!(file)
+          Enum.each(attrs, fn {key, value} -> :ok = ExAttr.set(file, key, value) end)
+          {name, file}
+        end
+
+      %{files: files}
+    end
+
+    test "matches values", %{tmp_dir: tmp_dir, files: files} do
+      assert {:ok, [files.a]} == ExAttr.find(tmp_dir, {:equals, "user.status", "pending"})
+      assert {:ok, [files.a, files.c]} == ExAttr.find(tmp_dir, {:prefix, "user.status", "pend"})
+      assert {:ok, [files.c]} == ExAttr.find(tmp_dir, {:regex, "user.status", ~r/RETRY$/i})
+      assert {:ok, [files.c]} == ExAttr.find(tmp_dir, {:size, "user.sum", :>=, 64})
+    end
+
+    test "combines predicates", %{tmp_dir: tmp_dir, path: path, files: files} do
+      assert {:ok, [tmp_dir, files.b, path]} == ExAttr.find(tmp_dir, {:missing, "user.sum"})
+      predicate = {:and, [{:exists, "user.sum"}, {:not, {:equals, "user.status", "pending"}}]}
+      assert {:ok, [files.c]} == ExAttr.find(tmp_dir, predicate)
+      predicate = {:or, [{:equals, "user.status", "done"}, {:size, "user.sum", :<, 10}]}
+      assert {:ok, [files.a, files.b]} == ExAttr.find(tmp_dir, predicate)
+    end
+
+    test "rejects bad predicates", %{tmp_dir: tmp_dir} do
+      assert {:error, {:invalid_name, :no_namespace}} = ExAttr.find(tmp_dir, {:exists, "sum"})
+      assert_raise ArgumentError, fn -> ExAttr.find(tmp_dir, {:regex, "user.sum", "("}) end
+      assert_raise ArgumentError, fn -> ExAttr.find(tmp_dir, {:bogus, "user.sum"}) end
+    end
+  end
+
+  describe "copy/3" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      dst = Path.join(tmp_dir, "dst.txt")
+      File.touch!(dst)
+      :ok = ExAttr.set(path, "user.a", "1")
+      :ok = ExAttr.set(path, "user.b", "2")
+      :ok = ExAttr.set(dst, "user.b", "old")
+      :ok = ExAttr.set(dst, "user.c", "3")
+      %{dst: dst}
+    end
+
+    test "overwrites by default", %{path: path, dst: dst} do
+      assert {:ok, %{copied: copied, skipped: [], removed: [], failed: []}} = ExAttr.copy(path, dst)
+      assert Enum.sort(copied) == ["user.a", "user.b"]
+      assert %{"user.a" => "1", "user.b" => "2", "user.c" => "3"} == ExAttr.dump!(dst)
+    end
+
+    test "skips conflicts and removes extras", %{path: path, dst: dst} do
+      assert {:ok, %{copied: ["user.a"], skipped: ["user.b"], removed: ["user.c"], failed: []}} =
+               ExAttr.copy(path, dst, on_conflict: :skip, remove_extra: true)
+      assert %{"user.a" => "1", "user.b" => "old"} == ExAttr.dump!(dst)
+    end
+
+    test "only copies the selected attributes", %{path: path, dst: dst} do
+      assert {:ok, %{copied: ["user.a"], removed: []}} = ExAttr.copy(path, dst, prefix: "user.a")
+      assert {:ok, %{copied: []}} = ExAttr.copy(path, dst, namespaces: [:trusted])
+    end
+
+    test "reports per attribute failures", %{tmp_dir: tmp_dir, path: path} do
+      link = Path.join(tmp_dir, "link")
+      :ok = File.ln_s(path, link)
+      assert {:ok, %{copied: [], failed: failed}} = ExAttr.copy(path, link)
+      assert [{"user.a", :eperm}, {"user.b", :eperm}] = Enum.sort(failed)
+      assert {:error, :enoent} = ExAttr.copy(Path.join(tmp_dir, "missing"), path)
+    end
+  end
+
+  describe "diff/3" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      other = Path.join(tmp_dir, "other.txt")
+      File.touch!(other)
+      :ok = ExAttr.set(path, "user.same", "1")
+      :ok = ExAttr.set(path, "user.gone", "2")
+      :ok = ExAttr.set(path, "user.edit", "old")
+      :ok = ExAttr.set(other, "user.same", "1")
+      :ok = ExAttr.set(other, "user.edit", "new")
+      :ok = ExAttr.set(other, "user.new", "3")
+      %{other: other}
+    end
+
+    test "compares two files", %{path: path, other: other} do
+      assert {:ok, diff} = ExAttr.diff(path, other)
+      assert %{added: %{"user.new" => "3"}, removed: %{"user.gone" => "2"}} = diff
+      assert %{changed: %{"user.edit" => {"old", "new"}}} = diff
+      assert {:ok, %{added: %{}, removed: %{}, changed: %{}}} = ExAttr.diff(path, path)
+    end
+
+    test "compares a file to a dump", %{path: path} do
+      snapshot = ExAttr.dump!(path)
+      :ok = ExAttr.remove(path, "user.same")
+      assert {:ok, %{added: %{}, removed: %{"user.same" => "1"}, changed: %{}}} =
+               ExAttr.diff(snapshot, path)
+      assert {:ok, %{added: %{}, removed: %{}, changed: %{}}} =
+               ExAttr.diff(path, snapshot, prefix: "user.edit")
+    end
+
+    test "patches a file into the other", %{path: path, other: other} do
+      assert :ok = ExAttr.patch(path, ExAttr.diff!(path, other))
+      assert ExAttr.dump!(path) == ExAttr.dump!(other)
+      assert :ok = ExAttr.patch(path, %{added: %{"user.extra" => "4"}})
+      assert {:ok, "4"} = ExAttr.get(path, "user.extra")
+    end
+
+    test "returns errors", %{tmp_dir: tmp_dir, path: path} do
+      missing = Path.join(tmp_dir, "missing")
+      assert {:error, :enoent} = ExAttr.diff(path, missing)
+      error = assert_raise ExAttr.Error, fn -> ExAttr.diff!(path, missing) end
+      assert %ExAttr.Error{reason: :enoent, path: ^missing} = error
+      assert {:error, {:invalid_name, :no_namespace}} = ExAttr.diff(path, %{"foo" => "1"})
+      assert {:error, :enodata} = ExAttr.patch(path, %{removed: %{"user.nope" => "1"}})
+    end
+  end
+
+  describe "export/2 and import/2" do
+    test "exports in getfattr format", %{tmp_dir: tmp_dir, path: path} do
+      :ok = ExAttr.set(path, "user.text", "a\"b")
+      :ok = ExAttr.set(path, "user.bin", <<0, 1, 2, 255>>)
+      :ok = ExAttr.set(path, "user.empty", "")
+
+      assert {:ok, dump} = ExAttr.export(tmp_dir)
+      lines = String.split(dump, "\n")
+      assert "# file: #{path}" in lines
+      assert ~S(user.text="a\042b") in lines
+      assert "user.bin=0sAAEC/w==" in lines
+      assert "user.empty" in lines
+      assert String.ends_with?(dump, "\n\n")
+
+      assert {:ok, hex} = ExAttr.export(path, encoding: :hex, prefix: "user.bin")
+      assert hex == "# file: #{path}\nuser.bin=0x000102ff\n\n"
+    end
+
+    test "round-trips through import", %{tmp_dir: tmp_dir, path: path} do
+      value = <<"line\nbreak", 0>>
+      :ok = ExAttr.set(path, "user.odd=name\\", value)
+      :ok = ExAttr.set(path, "user.bin", <<0, 1, 2, 255>>)
+      dump = ExAttr.export!(tmp_dir)
+
+      :ok = ExAttr.remove(path, "user.odd=name\\")
+      :ok = ExAttr.set(path, "user.bin", "changed")
+      assert :ok = ExAttr.import(dump)
+      assert {:ok, %{"user.odd=name\\" => ^value, "user.bin" => <<0, 1, 2, 255>>}} =
+               ExAttr.dump(path)
+    end
+
+    test "imports setfattr input", %{path: path} do
+      dump = "# comment\n# file: #{path}\nuser.a=plain\nuser.b=0xCAFE\nuser.c\n"
+      assert :ok = ExAttr.import(dump)
+      assert {:ok, %{"user.a" => "plain", "user.b" => <<0xCA, 0xFE>>, "user.c" => ""}} =
+               ExAttr.dump(path)
+    end
+
+    test "rejects malformed dumps without importing anything", %{path: path} do
+      assert {:error, {:invalid_dump, 1}} = ExAttr.import("user.a=\"1\"\n")
+      assert {:error, {:invalid_dump, 3}} =
+               ExAttr.import("# file: #{path}\nuser.a=\"1\"\nuser.b=0xabc\n")
+      assert {:error, {:invalid_name, :no_namespace}} =
+               ExAttr.import("# file: #{path}\nuser.a=\"1\"\nfoo=\"2\"\n")
+      assert {:ok, []} = ExAttr.list(path)
+    end
+  end
+
+  describe "snapshot/2 and restore/2" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      other = Path.join(tmp_dir, <<"other", 0xFF>>)
+      File.touch!(other)
+      :ok = ExAttr.set(path, "user.text", "bar")
+      :ok = ExAttr.set(path, "user.bin", <<0, 1, 2, 255>>)
+      :ok = ExAttr.set(other, <<"user.", 0xFE>>, "x")
+      %{other: other}
+    end
+
+    test "follows the json schema", %{other: other} do
+      name = <<"user.", 0xFE>>
+      assert {:ok, json} = ExAttr.snapshot(other, max_depth: 0)
+      assert json ==
+               ~s({"files":[{"path_base64":"#{Base.encode64(other)}","xattrs":[) <>
+                 ~s({"name_base64":"#{Base.encode64(name)}","value":"eA=="}]}],"version":1})
+    end
+
+    test "round-trips in both formats", %{tmp_dir: tmp_dir, path: path, other: other} do
+      for format <- [:json, :cbor] do
+        snapshot = ExAttr.snapshot!(tmp_dir, format: format)
+        :ok = ExAttr.remove(path, "user.bin")
+        :ok = ExAttr.remove(other, <<"user.", 0xFE>>)
+
+        assert :ok = ExAttr.restore(snapshot, format: format)
+        assert {:ok, %{"user.text" => "bar", "user.bin" => <<0, 1, 2, 255>>}} = ExAttr.dump(path)
+        assert {:ok, "x"} = ExAttr.get(other, <<"user.", 0xFE>>)
+      end
+    end
+
+    test "rejects malformed snapshots", %{tmp_dir: tmp_dir} do
+      cbor = ExAttr.snapshot!(tmp_dir, format: :cbor)
+      assert {:error, {:invalid_snapshot, :malformed}} = ExAttr.restore("{")
+      assert {:error, {:invalid_snapshot, :malformed}} = ExAttr.restore(cbor)
+      assert {:error, {:invalid_snapshot, :schema}} = ExAttr.restore(~s({"version":1}))
+      assert {:error, {:invalid_snapshot, :version}} =
+               ExAttr.restore(~s({"version":2,"files":[]}))
+    end
+  end
+
+  describe "tar archives" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      root = Path.join(tmp_dir, "root")
+      File.mkdir_p!(Path.join(root, "sub"))
+      file = Path.join(root, "sub/file.bin")
+      File.write!(file, "data")
+      :ok = ExAttr.set(root, "user.dir", "d")
+      :ok = ExAttr.set(file, "user.bin", <<0, ?\n, 255, ?=>>)
+      %{root: root, file: file, archive: path <> ".tar"}
+    end
+
+    test "round-trips attributes", %{tmp_dir: tmp_dir, root: root, archive: archive} do
+      assert :ok = ExAttr.create_tar(archive, root)
+      assert {:ok, entries} = ExAttr.list_tar(archive)
+      assert entries == [
+               {"root", %{"user.dir" => "d"}},
+               {"root/sub/file.bin", %{"user.bin" => <<0, ?\n, 255, ?=>>}}
+             ]
+      assert {:ok, [{"root/sub/file.bin", _}]} = ExAttr.list_tar(archive, prefix: "user.bin")
+
+      dest = Path.join(tmp_dir, "dest")
+      File.mkdir_p!(dest)
+      assert {:ok, %{failed: []}} = ExAttr.extract_tar(archive, dest)
+      extracted = Path.join(dest, "root/sub/file.bin")
+      assert File.read!(extracted) == "data"
+      assert {:ok, <<0, ?\n, 255, ?=>>} = ExAttr.get(extracted, "user.bin")
+      assert {:ok, "d"} = ExAttr.get(Path.join(dest, "root"), "user.dir")
+    end
+
+    test "only archives the selected attributes", %{root: root, file: file, archive: archive} do
+      :ok = ExAttr.set(file, "user.other", "x")
+      assert :ok = ExAttr.create_tar(archive, root, prefix: "user.other")
+      assert {:ok, [{"root/sub/file.bin", %{"user.other" => "x"}}]} = ExAttr.list_tar(archive)
+    end
+
+    test "only restores user attributes by default", %{tmp_dir: tmp_dir, archive: archive} do
+      pax_tar(archive, "f", "data", [{"user.ok", "1"}, {"trusted.x", "2"}])
+      assert {:ok, [{"f", %{"user.ok" => "1"}}]} = ExAttr.list_tar(archive)
+      assert {:ok, [{"f", %{"trusted.x" => "2"}}]} = ExAttr.list_tar(archive, prefix: "trusted.")
+      assert {:ok, [{"f", %{"user.ok" => "1", "trusted.x" => "2"}}]} =
+               ExAttr.list_tar(archive, prefix: nil)
+    end
+
+    test "reports attributes that can't be restored", %{tmp_dir: tmp_dir, archive: archive} do
+      pax_tar(archive, "f", "data", [{"user.ok", "1"}, {"bogus", "2"}])
+      dest = Path.join(tmp_dir, "dest")
+      File.mkdir_p!(dest)
+      extracted = Path.join(dest, "f")
+
+      assert {:ok, %{failed: [{^extracted, [{"bogus", {:invalid_name, :no_namespace}}]}]}} =
+               ExAttr.extract_tar(archive, dest, prefix: nil)
+      assert File.read!(extracted) == "data"
+      assert {:ok, "1"} = ExAttr.get(extracted, "user.ok")
+    end
+
+    test "restores read-only files", %{tmp_dir: tmp_dir, root: root, archive: archive} do
+      sub = Path.join(root, "sub")
+      :ok = ExAttr.set(sub, "user.sub", "s")
+      File.chmod!(Path.join(sub, "file.bin"), 0o444)
+      File.chmod!(sub, 0o555)
+      dest = Path.join(tmp_dir, "dest")
+      on_exit(fn -> for dir <- [sub, Path.join(dest, "root/sub")], do: File.chmod(dir, 0o755) end)
+      assert :ok = ExAttr.create_tar(archive, root)
+
+      File.mkdir_p!(dest)
+      assert {:ok, %{failed: []}} = ExAttr.extract_tar(archive, dest)
+      extracted = Path.join(dest, "root/sub/file.bin")
+      assert {:ok, <<0, ?\n, 255, ?=>>} = ExAttr.get(extracted, "user.bin")
+      assert {:ok, "s"} = ExAttr.get(Path.join(dest, "root/sub"), "user.sub")
+      assert File.stat!(extracted).mode |> Bitwise.band(0o777) == 0o444
+      assert File.stat!(Path.join(dest, "root/sub")).mode |> Bitwise.band(0o777) == 0o555
+    end
+
+    test "replaces archives once complete", %{tmp_dir: tmp_dir, root: root, archive: archive} do
+      File.write!(archive, "old")
+      assert {:error, :enoent} = ExAttr.create_tar(archive, Path.join(tmp_dir, "missing"))
+      assert File.read!(archive) == "old"
+
+      inside = Path.join(root, "root.tar")
+      File.write!(inside, "old")
+      assert :ok = ExAttr.create_tar(inside, root)
+      assert {:ok, names} = :erl_tar.table(String.to_charlist(inside))
+      assert Enum.sort(names) == [~c"root", ~c"root/sub", ~c"root/sub/file.bin"]
+      assert File.ls!(root) |> Enum.sort() == ["root.tar", "sub"]
+    end
+
+    test "returns errors", %{tmp_dir: tmp_dir, archive: archive} do
+      assert {:error, :enoent} = ExAttr.list_tar(archive)
+      assert {:error, :enoent} = ExAttr.create_tar(archive, Path.join(tmp_dir, "missing"))
+    end
+  end
+
+  describe "cp" do
+    test "copies data and attributes over the destination", %{tmp_dir: tmp_dir, path: path} do
+      File.write!(path, "data")
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      :ok = ExAttr.set(path, "user.bin", <<0, 255>>)
+      dst = Path.join(tmp_dir, "copy.txt")
+      File.write!(dst, "old")
+
+      assert {:ok, %{copied: copied, failed: []}} = ExAttr.cp(path, dst)
+      assert Enum.sort(copied) == ["user.bin", "user.foo"]
+      assert File.read!(dst) == "data"
+      assert {:ok, <<0, 255>>} = ExAttr.get(dst, "user.bin")
+      assert File.ls!(tmp_dir) |> Enum.sort() == ["copy.txt", "test.txt"]
+    end
+
+    test "only copies the selected attributes", %{tmp_dir: tmp_dir, path: path} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      :ok = ExAttr.set(path, "user.other", "x")
+      dst = Path.join(tmp_dir, "copy.txt")
+
+      assert {:ok, %{copied: ["user.foo"]}} = ExAttr.cp(path, dst, prefix: "user.foo")
+      assert {:ok, ["user.foo"]} = ExAttr.list(dst)
+    end
+
+    test "leaves the destination untouched on error", %{tmp_dir: tmp_dir, path: path} do
+      dst = Path.join(tmp_dir, "copy.txt")
+      assert {:error, :enoent} = ExAttr.cp(Path.join(tmp_dir, "missing"), dst)
+      assert {:error, :enoent} = ExAttr.cp(path, Path.join(tmp_dir, "missing/copy.txt"))
+      refute File.exists?(dst)
+    end
+  end
+
+  describe "write" do
+    test "writes contents and attributes over the destination", %{path: path} do
+      File.write!(path, "old")
+
+      attrs = %{"user.foo" => "bar", "user.bin" => <<0, 255>>}
+      assert :ok = ExAttr.write(path, ["da", "ta"], attrs)
+      assert File.read!(path) == "data"
+      assert {:ok, ^attrs} = ExAttr.dump(path)
+      assert File.ls!(Path.dirname(path)) == ["test.txt"]
+    end
+
+    test "keeps the permissions of the file it replaces", %{path: path} do
+      File.chmod!(path, 0o640)
+      assert :ok = ExAttr.write(path, "data", %{"user.foo" => "bar"})
+      assert File.stat!(path).mode |> Bitwise.band(0o777) == 0o640
+    end
+
+    test "writes nothing on error", %{tmp_dir: tmp_dir} do
+      dst = Path.join(tmp_dir, "new.txt")
+      assert {:error, {:invalid_name, :no_namespace}} = ExAttr.write(dst, "data", %{"foo" => "bar"})
+      assert {:error, :enoent} = ExAttr.write(Path.join(tmp_dir, "missing/new.txt"), "data", %{})
+      refute File.exists?(dst)
+    end
+  end
+
+  describe "errors" do
+    test "are returned as posix atoms", %{tmp_dir: tmp_dir, path: path} do
+      long_name = "user." <> String.duplicate("a", 300)
+      assert {:error, :enotdir} = ExAttr.get(Path.join(path, "child"), "user.foo")
+      long_path = Path.join(tmp_dir, String.duplicate("a", 300))
+      assert {:error, :enametoolong} = ExAttr.get(long_path, "user.foo")
+      assert {:error, {:invalid_name, :too_long}} = ExAttr.get(path, long_name)
+    end
+
+    test "bang functions raise with errno, syscall and name", %{path: path} do
+      error = assert_raise ExAttr.Error, fn -> ExAttr.remove!(path, "user.missing") end
+      assert %ExAttr.Error{reason: :enodata, syscall: :lremovexattr, name: "user.missing"} = error
+      assert is_integer(error.errno)
+      assert error.path == path
+
+      missing = Path.join(path, "missing")
+      error = assert_raise ExAttr.Error, fn -> ExAttr.get!(missing, "user.foo", follow_symlinks: true) end
+      assert %ExAttr.Error{reason: :enotdir, syscall: :getxattr, name: "user.foo"} = error
+    end
+
+    test "eloop on symlink cycles", %{tmp_dir: tmp_dir} do
+      a = Path.join(tmp_dir, "a")
+      b = Path.join(tmp_dir, "b")
+      :ok = File.ln_s(a, b)
+      :ok = File.ln_s(b, a)
+      assert {:error, :eloop} = ExAttr.get(a, "user.foo", follow_symlinks: true)
+    end
+  end
+
+  describe "validation" do
+    test "rejects bad names before the syscall", %{path: path} do
+      assert {:error, {:invalid_name, :empty}} = ExAttr.get(path, "")
+      assert {:error, {:invalid_name, :no_namespace}} = ExAttr.set(path, "foo", "bar")
+      assert {:error, {:invalid_name, :unknown_namespace}} = ExAttr.remove(path, "usr.foo")
+      assert {:error, {:invalid_name, :nul_byte}} = ExAttr.get(path, <<"user.a", 0, "b">>)
+      assert {:error, {:invalid_name, :empty_key}} = ExAttr.size(path, "user.")
+    end
+
+    test "rejects oversize values", %{path: path} do
+      value = :binary.copy("x", 65_537)
+      assert {:error, {:invalid_value, :too_big}} = ExAttr.set(path, "user.foo", value)
+      handle = ExAttr.Handle.open!(path)
+      error = assert_raise ExAttr.Error, fn -> ExAttr.set!(handle, "user.foo", value) end
+      assert %ExAttr.Error{reason: {:invalid_value, :too_big}, errno: nil, name: "user.foo"} = error
+    end
+  end
+
+  describe "sizes" do
+    test "size/3 returns the value size or nil", %{path: path} do
+      assert {:ok, nil} = ExAttr.size(path, "user.foo")
+      :ok = ExAttr.set(path, "user.foo", :binary.copy("x", 1000))
+      assert {:ok, 1000} = ExAttr.size(path, "user.foo")
+      :ok = ExAttr.set(path, "user.empty", "")
+      assert {:ok, 0} = ExAttr.size(path, "user.empty")
+    end
+
+    test "list_with_sizes/2 returns every name with its size", %{path: path} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      :ok = ExAttr.set(path, "user.test", "example")
+      assert {:ok, sizes} = ExAttr.list_with_sizes(path)
+      assert Enum.sort(sizes) == [{"user.foo", 3}, {"user.test", 7}]
+    end
+  end
+
+  describe "set/4 modes" do
+    test "create only sets missing attributes", %{path: path} do
+      assert :ok = ExAttr.set(path, "user.foo", "bar", mode: :create)
+      assert {:error, :eexist} = ExAttr.set(path, "user.foo", "baz", mode: :create)
+      assert {:ok, "bar"} = ExAttr.get(path, "user.foo")
+    end
+
+    test "replace only sets existing attributes", %{path: path} do
+      assert {:error, :enodata} = ExAttr.set(path, "user.foo", "bar", mode: :replace)
+      assert {:ok, nil} = ExAttr.get(path, "user.foo")
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      assert :ok = ExAttr.set(path, "user.foo", "baz", mode: :replace)
+      assert {:ok, "baz"} = ExAttr.get(path, "user.foo")
+    end
+
+    test "removing a missing attribute only fails in replace mode", %{path: path} do
+      assert :ok = ExAttr.set(path, "user.foo", nil)
+      assert {:error, :enodata} = ExAttr.set(path, "user.foo", nil, mode: :replace)
+    end
+  end
+
+  describe "handles" do
+    test "support every operation", %{path: path} do
+      handle = ExAttr.Handle.open!(path)
+      assert :ok = ExAttr.set(handle, "user.foo", "bar")
+      assert {:error, :eexist} = ExAttr.set(handle, "user.foo", "baz", mode: :create)
+      assert {:ok, "bar"} = ExAttr.get(handle, "user.foo")
+      assert {:ok, ["user.foo"]} = ExAttr.list(handle)
+      assert {:ok, %{"user.foo" => "bar"}} = ExAttr.dump(handle)
+      assert :ok = ExAttr.remove(handle, "user.foo")
+      assert {:ok, nil} = ExAttr.get(path, "user.foo")
+    end
+
+    test "keep applying to the same file after a rename", %{tmp_dir: tmp_dir, path: path} do
+      handle = ExAttr.Handle.open!(path)
+      File.rename!(path, Path.join(tmp_dir, "renamed.txt"))
+      File.touch!(path)
+      :ok = ExAttr.set(handle, "user.foo", "bar")
+      assert {:ok, nil} = ExAttr.get(path, "user.foo")
+      assert {:ok, "bar"} = ExAttr.get(Path.join(tmp_dir, "renamed.txt"), "user.foo")
+    end
+
+    test "errors carry the handle's path", %{tmp_dir: tmp_dir, path: path} do
+      assert {:error, :enoent} = ExAttr.Handle.open(Path.join(tmp_dir, "missing"))
+      handle = ExAttr.Handle.open!(path)
+      error = assert_raise ExAttr.Error, fn -> ExAttr.remove!(handle, "user.missing") end
+      assert %ExAttr.Error{reason: :enodata, syscall: :fremovexattr, path: ^path} = error
+    end
+  end
+
+  describe "symlinks" do
+    setup %{tmp_dir: tmp_dir, path: path} do
+      link = Path.join(tmp_dir, "link")
+      dangling = Path.join(tmp_dir, "dangling")
+      :ok = File.ln_s(path, link)
+      :ok = File.ln_s(Path.join(tmp_dir, "missing"), dangling)
+      %{link: link, dangling: dangling}
+    end
+
+    test "operate on the symlink itself by default", %{path: path, link: link} do
+      :ok = ExAttr.set(path, "user.foo", "bar")
+      assert {:ok, nil} = ExAttr.get(link, "user.foo")
+      refute "user.foo" in ExAttr.list!(link)
+      assert {:error, :eperm} = ExAttr.set(link, "user.foo", "baz")
+    end
+
+    test "operate on the target when following", %{path: path, link: link} do
+      opts = [follow_symlinks: true]
+      :ok = ExAttr.set(link, "user.foo", "bar", opts)
+      assert {:ok, "bar"} = ExAttr.get(path, "user.foo")
+      assert {:ok, "bar"} = ExAttr.get(link, "user.foo", opts)
+      assert %{"user.foo" => "bar"} = ExAttr.dump!(link, opts)
+      :ok = ExAttr.remove(link, "user.foo", opts)
+      assert {:ok, nil} = ExAttr.get(path, "user.foo")
+    end
+
+    test "dangling symlinks only fail when following", %{dangling: dangling} do
+      assert {:ok, nil} = ExAttr.get(dangling, "user.foo")
+      assert {:ok, []} = ExAttr.list(dangling)
+      assert {:error, :enoent} = ExAttr.get(dangling, "user.foo", follow_symlinks: true)
+      assert {:error, :enoent} = ExAttr.list(dangling, follow_symlinks: true)
+    end
+  end
+
+  describe "dirty scheduling" do
+    # Opening a FIFO for reading blocks in open(2) until a writer shows up, the same way a
+    # call into a hung FUSE or NFS mount would
+    test "blocked filesystem calls do not block other processes", %{tmp_dir: tmp_dir} do
+      # Enough blocked callers to tie up every normal scheduler if the NIFs ran on them
+      fifos =
+        for i <- 1..(System.schedulers_online() * 2) do
+          fifo = Path.join(tmp_dir, "fifo-#{i}")
+          {_, 0} = System.cmd("mkfifo", [fifo])
+          fifo
+        end
+
+      blockers =
+        for fifo <- fifos do
+          Task.async(fn -> ExAttr.cp(fifo, fifo <> ".copy") end)
+        end
+
+      Process.sleep(50)
+
+      {elapsed, :pong} = :timer.tc(fn ->
+        Task.async(fn -> :pong end) |> Task.await()
+      end)
+
+      assert elapsed < 50_000
+
+      # Open every FIFO for writing at once, releasing the callers whatever order they run in
+      writers =
+        for fifo <- fifos do
+          Task.async(fn -> System.cmd("sh", ["-c", ": > \"$1\"", "sh", fifo]) end)
+        end
+
+      Task.await_many(writers, :infinity)
+      assert Enum.all?(Task.await_many(blockers, :infinity), &match?({:ok, _}, &1))
+    end
+  end
+
+  defp touch(dir, name) do
+    path = Path.join(dir, name)
+    File.touch!(path)
+    path
+  end
+
+  defp receive_batches(tag) do
+    receive do
+      {^tag, {:batch, batch}} -> [batch | receive_batches(tag)]
+    after
+      0 -> []
+    end
+  end
+
+  # Acknowledges every batch, returning whatever ends the scan
+  defp receive_until_batches_end(scan, tag) do
+    receive do
+      {^tag, {:batch, _}} ->
+        :ok = ExAttr.Nif.ack_tree_scan(scan)
+        receive_until_batches_end(scan, tag)
+
+      {^tag, message} ->
+        message
+    after
+      1000 -> flunk("the scan never ended")
+    end
+  end
+
+  # Threads of the VM that belong to a parallel tree scan, which are all named after it
+  defp scan_threads do
+    Path.wildcard("/proc/self/task/*/comm")
+    |> Enum.count(&(File.read!(&1) == "ex_attr_scan\n"))
+  end
+
+  defp eventually(fun, tries \\ 50) do
+    cond do
+      fun.() -> true
+      tries == 0 -> false
+      true ->
+        Process.sleep(20)
+        eventually(fun, tries - 1)
+    end
+  end
+
+  # A ustar archive of a single file preceded by a PAX header of `SCHILY.xattr` records,
+  # which can hold names no file could ever have
+  defp pax_tar(path, name, contents, xattrs) do
+    records =
+      for {key, value} <- xattrs, into: "", do: pax_record(" SCHILY.xattr.#{key}=#{value}\n")
+
+    File.write!(path, [
+      tar_header("PaxHeader", "x", records),
+      tar_header(name, "0", contents),
+      :binary.copy(<<0>>, 1024)
+    ])
+  end
+
+  # Every record starts with its own length, which counts the digits of the length itself
+  defp pax_record(rest, len \\ 0) do
+    total = byte_size(rest) + byte_size(Integer.to_string(len))
+    if total == len, do: "#{len}#{rest}", else: pax_record(rest, total)
+  end
+
+  # A header block followed by the data padded to a whole number of blocks
+  defp tar_header(name, type, data) do
+    octal = fn n, width -> String.pad_leading(Integer.to_string(n, 8), width - 1, "0") <> <<0>> end
+    pad = fn bin, width -> bin <> :binary.copy(<<0>>, width - byte_size(bin)) end
+
+    header =
+      IO.iodata_to_binary([
+        pad.(name, 100), octal.(0o644, 8), octal.(0, 8), octal.(0, 8),
+        octal.(byte_size(data), 12), octal.(0, 12), "        ", type,
+        pad.("", 100), "ustar", 0, "00", pad.("", 247)
+      ])
+
+    checksum = header |> :binary.bin_to_list() |> Enum.sum()
+    <<before::binary-148, _::binary-8, rest::binary>> = header
+    checksum = String.pad_leading(Integer.to_string(checksum, 8), 6, "0") <> <<0, ?\s>>
+    data = pad.(data, byte_size(data) + rem(512 - rem(byte_size(data), 512), 512))
+    [before, checksum, rest, data]
+  end
 end